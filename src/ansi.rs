@@ -0,0 +1,171 @@
+use ratatui::{
+	style::{Color, Modifier, Style},
+	text::{Line, Span, Text}
+};
+
+/// Applies the parameters of an SGR sequence, `1;38;5;208` of `\x1b[1;38;5;208m`. Colons
+/// are taken as semicolons and unknown codes are skipped
+fn apply_sgr(mut style: Style, params: &str) -> Style {
+	// `\x1b[m` is a reset too
+	if params.is_empty() {
+		return Style::default();
+	}
+
+	// an empty code is 0, one that doesn't parse matches nothing
+	let mut codes = params.split([';', ':']).map(|code| match code {
+		"" => 0,
+		code => code.parse::<u16>().unwrap_or(u16::MAX)
+	});
+
+	while let Some(code) = codes.next() {
+		style = match code {
+			0 => Style::default(),
+			1 => style.add_modifier(Modifier::BOLD),
+			2 => style.add_modifier(Modifier::DIM),
+			3 => style.add_modifier(Modifier::ITALIC),
+			4 => style.add_modifier(Modifier::UNDERLINED),
+			5 => style.add_modifier(Modifier::SLOW_BLINK),
+			6 => style.add_modifier(Modifier::RAPID_BLINK),
+			7 => style.add_modifier(Modifier::REVERSED),
+			8 => style.add_modifier(Modifier::HIDDEN),
+			9 => style.add_modifier(Modifier::CROSSED_OUT),
+			21 | 22 => style.remove_modifier(Modifier::BOLD | Modifier::DIM),
+			23 => style.remove_modifier(Modifier::ITALIC),
+			24 => style.remove_modifier(Modifier::UNDERLINED),
+			25 => style.remove_modifier(Modifier::SLOW_BLINK | Modifier::RAPID_BLINK),
+			27 => style.remove_modifier(Modifier::REVERSED),
+			28 => style.remove_modifier(Modifier::HIDDEN),
+			29 => style.remove_modifier(Modifier::CROSSED_OUT),
+			30..=37 => style.fg(Color::Indexed(code as u8 - 30)),
+			38 => match extended_color(&mut codes) {
+				Some(color) => style.fg(color),
+				None => style
+			},
+			39 => style.fg(Color::Reset),
+			40..=47 => style.bg(Color::Indexed(code as u8 - 40)),
+			48 => match extended_color(&mut codes) {
+				Some(color) => style.bg(color),
+				None => style
+			},
+			49 => style.bg(Color::Reset),
+			90..=97 => style.fg(Color::Indexed(code as u8 - 90 + 8)),
+			100..=107 => style.bg(Color::Indexed(code as u8 - 100 + 8)),
+			_ => style
+		};
+	}
+
+	style
+}
+
+/// The color after a 38 or 48, `5;n` from the 256 color palette or `2;r;g;b`
+fn extended_color(codes: &mut impl Iterator<Item = u16>) -> Option<Color> {
+	let mut next = || codes.next().and_then(|code| u8::try_from(code).ok());
+	match next()? {
+		5 => Some(Color::Indexed(next()?)),
+		2 => Some(Color::Rgb(next()?, next()?, next()?)),
+		_ => None
+	}
+}
+
+/// Splits `s` into spans styled by its SGR sequences, any other escape sequence and control
+/// character but tab is dropped. `style` is where the line starts and is left at where it
+/// ends, colors carry over to the next line like they do in a terminal
+pub fn parse_line(s: &str, style: &mut Style) -> Line<'static> {
+	let mut spans = vec![];
+	let mut content = String::new();
+	let mut chars = s.chars().peekable();
+
+	while let Some(c) = chars.next() {
+		match c {
+			'\x1b' => match chars.next() {
+				// CSI, parameters up to a final byte in `@..~`
+				Some('[') => {
+					let mut params = String::new();
+					let mut sgr = false;
+					for c in chars.by_ref() {
+						if ('\x40'..='\x7e').contains(&c) {
+							sgr = c == 'm';
+							break;
+						}
+						params.push(c);
+					}
+					if sgr {
+						if !content.is_empty() {
+							spans.push(Span::styled(std::mem::take(&mut content), *style));
+						}
+						*style = apply_sgr(*style, &params);
+					}
+				}
+				// OSC, e.g. hyperlinks, ends with BEL or ESC \
+				Some(']') => {
+					while let Some(c) = chars.next() {
+						if c == '\x07' {
+							break;
+						}
+						if c == '\x1b' {
+							chars.next_if_eq(&'\\');
+							break;
+						}
+					}
+				}
+				_ => {}
+			},
+			'\t' => content.push(c),
+			c if c.is_control() => {}
+			c => content.push(c)
+		}
+	}
+	if !content.is_empty() {
+		spans.push(Span::styled(content, *style));
+	}
+
+	Line::from(spans)
+}
+
+/// Every line of `s` with the escape sequences turned into styles
+pub fn parse_text(s: &str) -> Text<'static> {
+	let mut style = Style::default();
+	Text::from(
+		s.lines()
+			.map(|line| parse_line(line, &mut style))
+			.collect::<Vec<_>>()
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use rstest::*;
+
+	use super::*;
+
+	#[rstest]
+	#[case::reset("1;0", Style::new())]
+	#[case::empty("", Style::new())]
+	#[case::bold_red("1;31", Style::new().fg(Color::Indexed(1)).add_modifier(Modifier::BOLD))]
+	#[case::bright("92", Style::new().fg(Color::Indexed(10)))]
+	#[case::indexed("38;5;208", Style::new().fg(Color::Indexed(208)))]
+	#[case::rgb_bg("48;2;1;2;3", Style::new().bg(Color::Rgb(1, 2, 3)))]
+	#[case::colons("38:5:208", Style::new().fg(Color::Indexed(208)))]
+	#[case::truncated("38;5", Style::new())]
+	#[case::unknown("999;4", Style::new().add_modifier(Modifier::UNDERLINED))]
+	fn sgr(#[case] params: &str, #[case] expected: Style) {
+		assert_eq!(expected, apply_sgr(Style::new(), params));
+	}
+
+	#[test]
+	fn spans_and_carried_style() {
+		let mut style = Style::new();
+		let line = parse_line("a\x1b[31mb\x1b]8;;http://x\x07c\x1b[Kd\r", &mut style);
+		let red = Style::new().fg(Color::Indexed(1));
+		assert_eq!(
+			Line::from(vec![Span::raw("a"), Span::styled("bcd", red)]),
+			line
+		);
+		assert_eq!(red, style);
+
+		let text = parse_text("\x1b[1mx\ny\x1b[0m\nz");
+		assert_eq!(3, text.lines.len());
+		assert!(text.lines[1].spans[0].style.add_modifier.contains(Modifier::BOLD));
+		assert_eq!(Style::new(), text.lines[2].spans[0].style);
+	}
+}
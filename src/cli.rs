@@ -0,0 +1,218 @@
+use std::{env, io::IsTerminal, path::PathBuf};
+
+use anyhow::anyhow;
+use chin_tools::wrapper::anyhow::AResult;
+use clap::Parser;
+
+use crate::{
+	dirwalker::{FindType, Source},
+	config::Config,
+	output::{OutputFormat, PathFormat},
+	AppState
+};
+
+/// A fuzzy file picker, prints the accepted paths to stdout
+#[derive(Parser, Debug)]
+#[command(name = "ffp", version, about)]
+pub struct Cli {
+	/// Directory to start in, defaults to the current directory
+	pub dir: Option<PathBuf>,
+
+	/// Start with this query
+	#[arg(short, long, default_value = "")]
+	pub query: String,
+
+	/// `find` walks the directory recursively, `ls` lists only its entries [default: find]
+	#[arg(short, long, value_enum)]
+	pub mode: Option<FindType>,
+
+	/// Include dotfiles
+	#[arg(short = 'H', long, overrides_with = "no_hidden")]
+	pub hidden: bool,
+
+	/// Leave dotfiles out
+	#[arg(long, overrides_with = "hidden")]
+	pub no_hidden: bool,
+
+	/// Honor .gitignore, .ignore and the git excludes
+	#[arg(long, overrides_with = "no_ignore")]
+	pub ignore: bool,
+
+	/// Do not honor .gitignore, .ignore and the git excludes
+	#[arg(long, overrides_with = "ignore")]
+	pub no_ignore: bool,
+
+	/// Rank matches by score
+	#[arg(long, overrides_with = "no_sort")]
+	pub sort: bool,
+
+	/// Keep matches in the order they were found instead of ranking them by score
+	#[arg(long, overrides_with = "sort")]
+	pub no_sort: bool,
+
+	/// Show the preview pane
+	#[arg(long, overrides_with = "no_preview")]
+	pub show_preview: bool,
+
+	/// Hide the preview pane
+	#[arg(long, overrides_with = "show_preview")]
+	pub no_preview: bool,
+
+	/// Preview with a command instead of the builtin viewers, e.g. `bat --color=always {sel}`.
+	/// Replaces the preview commands from the config
+	#[arg(long, value_name = "COMMAND")]
+	pub preview: Option<String>,
+
+	/// Syntax highlighting theme of the preview, e.g. `base16-ocean.dark`
+	#[arg(long, value_name = "NAME")]
+	pub theme: Option<String>,
+
+	/// Read the candidates from stdin, implied when stdin is not a terminal
+	#[arg(long)]
+	pub stdin: bool,
+
+	/// Show the ANSI colors of the candidates from stdin, e.g. `rg --color=always`, instead of
+	/// taking the escape codes as text
+	#[arg(long)]
+	pub ansi: bool,
+
+	/// Print absolute paths instead of paths relative to the start directory
+	#[arg(short, long)]
+	pub absolute: bool,
+
+	/// Terminate the printed paths with NUL instead of newline
+	#[arg(long)]
+	pub print0: bool,
+
+	/// Accept right away when there is only one match once all candidates are loaded
+	#[arg(short = '1', long)]
+	pub select_1: bool,
+
+	/// Exit right away when there is no match once all candidates are loaded
+	#[arg(short = '0', long)]
+	pub exit_0: bool,
+
+	/// Run a command on a key, e.g. `ctrl-e:execute($EDITOR {sel})` or
+	/// `ctrl-y:execute-silent(wl-copy {sel})`. {sel}, {input} and {cwd} are replaced with the
+	/// quoted selection, query and directory
+	#[arg(long = "bind", value_name = "KEY:ACTION")]
+	pub binds: Vec<String>,
+
+	/// Config file to use instead of $XDG_CONFIG_HOME/ffp/config.toml
+	#[arg(long, value_name = "PATH")]
+	pub config: Option<PathBuf>
+}
+
+/// The value of a `--flag`/`--no-flag` pair, `None` leaves the config alone. clap keeps only
+/// the last of the two
+fn flag(yes: bool, no: bool) -> Option<bool> {
+	match (yes, no) {
+		(true, _) => Some(true),
+		(_, true) => Some(false),
+		_ => None
+	}
+}
+
+impl Cli {
+	pub fn into_state(self) -> AResult<AppState> {
+		let config = Config::load(self.config.clone())?;
+		self.with_config(config)
+	}
+
+	/// Values from the command line win over the ones from the config
+	fn with_config(self, config: Config) -> AResult<AppState> {
+		let cwd = match self.dir {
+			Some(dir) => dir.canonicalize()?,
+			None => env::current_dir()?
+		};
+
+		let mut keymap = config.keymap()?;
+		for bind in self.binds.iter() {
+			keymap
+				.bind_str(bind)
+				.map_err(|err| anyhow!("invalid --bind `{}`: {}", bind, err))?;
+		}
+
+		// crossterm reads keys from /dev/tty when stdin is not a terminal, so it is free for us
+		let source = if self.stdin || !std::io::stdin().is_terminal() {
+			Source::Stdin
+		} else {
+			Source::Walk
+		};
+
+		let mut preview = config.preview;
+		preview.enabled = flag(self.show_preview, self.no_preview).unwrap_or(preview.enabled);
+		if self.theme.is_some() {
+			preview.theme = self.theme;
+		}
+		if let Some(command) = self.preview {
+			preview.command = Some(command);
+			preview.commands.clear();
+		}
+
+		Ok(AppState {
+			cwd: cwd.to_string_lossy().to_string(),
+			query: self.query,
+			source,
+			ansi: self.ansi,
+			show_mode: self.mode.unwrap_or(config.general.mode),
+			show_hidden: flag(self.hidden, self.no_hidden).unwrap_or(config.general.hidden),
+			ignore: flag(self.ignore, self.no_ignore).unwrap_or(config.general.ignore),
+			sort: flag(self.sort, self.no_sort).unwrap_or(config.general.sort),
+			theme: config.theme,
+			preview,
+			output: OutputFormat {
+				path: if self.absolute {
+					PathFormat::Absolute
+				} else {
+					PathFormat::Relative
+				},
+				print0: self.print0
+			},
+			select_1: self.select_1,
+			exit_0: self.exit_0,
+			keymap
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use rstest::*;
+
+	use super::*;
+
+	const FLIPPED: &str = r#"
+[general]
+hidden = true
+ignore = false
+sort = false
+
+[preview]
+enabled = false
+"#;
+
+	#[rstest]
+	#[case::defaults("", &[], (false, true, true, true))]
+	#[case::flags_on("", &["-H", "--no-ignore", "--no-sort", "--no-preview"], (true, false, false, false))]
+	#[case::config(FLIPPED, &[], (true, false, false, false))]
+	#[case::flags_off(FLIPPED, &["--no-hidden", "--ignore", "--sort", "--show-preview"], (false, true, true, true))]
+	#[case::last_wins("", &["--hidden", "--no-hidden", "--no-sort", "--sort"], (false, true, true, true))]
+	fn flags_override_config(
+		#[case] config: &str,
+		#[case] args: &[&str],
+		#[case] expected: (bool, bool, bool, bool)
+	) {
+		let cli = Cli::try_parse_from(std::iter::once("ffp").chain(args.iter().copied())).unwrap();
+		let state = cli.with_config(Config::parse(config).unwrap()).unwrap();
+		assert_eq!(
+			expected,
+			(
+				state.show_hidden,
+				state.ignore,
+				state.sort,
+				state.preview.enabled
+			)
+		);
+	}
+}
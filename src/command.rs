@@ -0,0 +1,222 @@
+use std::{path::Path, process::Stdio};
+
+use chin_tools::wrapper::anyhow::AResult;
+use tokio::process::{Child, Command};
+
+use crate::{
+	constant::{PH_INPUT, PH_SELECTED, PH_WORK_DIR},
+	fileinfo::FilePath
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecMode {
+	/// Suspend the tui and hand the terminal over to the command
+	Foreground,
+	/// Detach the command, only its exit status is reported
+	Background,
+	/// Pipe stdout and stderr back, the command runs in its own process group so that
+	/// [`ProcessGroup`] stops its whole pipeline
+	Capture
+}
+
+/// A shell command with `{input}`, `{sel}` and `{cwd}` placeholders
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandTemplate {
+	template: String,
+	pub mode: ExecMode
+}
+
+/// The values substituted into a [`CommandTemplate`]
+pub struct Placeholders<'a> {
+	pub input: &'a str,
+	pub selected: &'a [FilePath],
+	pub cwd: &'a str
+}
+
+/// Quotes `s` for a POSIX shell, `it's` becomes `'it'\''s'`
+pub fn shell_quote(s: &str) -> String {
+	if !s.is_empty()
+		&& s.chars()
+			.all(|c| c.is_ascii_alphanumeric() || "-_./=+,:@%".contains(c))
+	{
+		return s.to_string();
+	}
+
+	format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+impl CommandTemplate {
+	pub fn new(template: &str, mode: ExecMode) -> Self {
+		Self {
+			template: template.to_string(),
+			mode
+		}
+	}
+
+	/// Every placeholder is replaced with a quoted value, `{sel}` expands to all the marked
+	/// paths separated by spaces. The template is scanned once, a value that looks like a
+	/// placeholder is never expanded again
+	pub fn expand(&self, ph: &Placeholders) -> String {
+		let cwd = Path::new(ph.cwd);
+		let selected = ph
+			.selected
+			.iter()
+			.map(|path| shell_quote(&path.relative(cwd)))
+			.collect::<Vec<_>>()
+			.join(" ");
+
+		let values = [
+			(PH_INPUT, shell_quote(ph.input)),
+			(PH_SELECTED, selected),
+			(PH_WORK_DIR, shell_quote(ph.cwd))
+		];
+
+		let mut expanded = String::with_capacity(self.template.len());
+		let mut rest = self.template.as_str();
+		while let Some((at, placeholder, value)) = values
+			.iter()
+			.filter_map(|(placeholder, value)| Some((rest.find(placeholder)?, placeholder, value)))
+			.min_by_key(|(at, ..)| *at)
+		{
+			expanded.push_str(&rest[..at]);
+			expanded.push_str(value);
+			rest = &rest[at + placeholder.len()..];
+		}
+		expanded.push_str(rest);
+		expanded
+	}
+
+	pub fn command(&self, ph: &Placeholders) -> Command {
+		let mut command = Command::new("sh");
+		command.arg("-c").arg(self.expand(ph)).current_dir(ph.cwd);
+
+		match self.mode {
+			ExecMode::Foreground => {
+				// stdout may be captured by `$(ffp)`, the command wants the terminal
+				if let Ok(tty) = std::fs::File::options().read(true).write(true).open("/dev/tty") {
+					if let Ok(tty_out) = tty.try_clone() {
+						command.stdin(tty).stdout(tty_out);
+					}
+				}
+			}
+			ExecMode::Background => {
+				command
+					.stdin(Stdio::null())
+					.stdout(Stdio::null())
+					.stderr(Stdio::null())
+					// its own process group, so it survives us and our terminal signals
+					.process_group(0);
+			}
+			ExecMode::Capture => {
+				command
+					.stdin(Stdio::null())
+					.stdout(Stdio::piped())
+					.stderr(Stdio::piped())
+					.process_group(0)
+					.kill_on_drop(true);
+			}
+		}
+
+		command
+	}
+}
+
+/// Kills the process group of an [`ExecMode::Capture`] command once dropped, a pipeline
+/// leaves more than the shell behind
+pub struct ProcessGroup {
+	pgid: libc::pid_t,
+	reaped: bool
+}
+
+impl ProcessGroup {
+	/// The group `child` leads, `None` once it was reaped
+	pub fn of(child: &Child) -> Option<Self> {
+		child
+			.id()
+			.and_then(|pid| libc::pid_t::try_from(pid).ok())
+			.map(|pgid| Self { pgid, reaped: false })
+	}
+
+	/// The leader was waited for, its pgid may belong to another group by now
+	pub fn set_reaped(&mut self) {
+		self.reaped = true;
+	}
+
+	pub fn kill(&self) {
+		if self.reaped {
+			return;
+		}
+		// the group is gone when everything in it exited already, nothing to report then
+		unsafe {
+			libc::killpg(self.pgid, libc::SIGKILL);
+		}
+	}
+}
+
+impl Drop for ProcessGroup {
+	fn drop(&mut self) {
+		self.kill();
+	}
+}
+
+/// Runs `cmd` to the end, an error describes a failure to start or a non zero exit
+pub async fn run(mut command: Command) -> AResult<()> {
+	let status = command.status().await?;
+	if !status.success() {
+		anyhow::bail!("command exited with {}", status);
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use rstest::*;
+
+	use super::*;
+
+	#[rstest]
+	#[case::plain("src/main.rs", "src/main.rs")]
+	#[case::space("my file", "'my file'")]
+	#[case::quote("it's", r"'it'\''s'")]
+	#[case::dollar("$HOME", "'$HOME'")]
+	#[case::empty("", "''")]
+	fn quote(#[case] input: &str, #[case] expected: &str) {
+		assert_eq!(expected, shell_quote(input));
+	}
+
+	#[test]
+	fn expand_placeholders() {
+		let selected = vec![
+			FilePath::from_line("a b.txt".to_string(), "/tmp"),
+			FilePath::from_line("c.txt".to_string(), "/tmp"),
+		];
+		let template = CommandTemplate::new("cd {cwd} && vim {sel} # {input}", ExecMode::Foreground);
+		let expanded = template.expand(&Placeholders {
+			input: "some query",
+			selected: &selected,
+			cwd: "/tmp"
+		});
+		assert_eq!("cd /tmp && vim 'a b.txt' c.txt # 'some query'", expanded);
+	}
+
+	#[rstest]
+	#[case::input_of_sel("{sel}", "x.txt", "/tmp", "cd /tmp && cat x.txt # '{sel}'")]
+	#[case::input_of_cwd("{cwd}", "x.txt", "/tmp", "cd /tmp && cat x.txt # '{cwd}'")]
+	#[case::path_of_cwd("q", "{cwd}.txt", "/tmp", "cd /tmp && cat '{cwd}.txt' # q")]
+	#[case::cwd_of_input("q", "x.txt", "/tmp/{input}", "cd '/tmp/{input}' && cat x.txt # q")]
+	fn expand_once(
+		#[case] input: &str,
+		#[case] path: &str,
+		#[case] cwd: &str,
+		#[case] expected: &str
+	) {
+		let selected = vec![FilePath::from_line(path.to_string(), cwd)];
+		let template = CommandTemplate::new("cd {cwd} && cat {sel} # {input}", ExecMode::Capture);
+		let expanded = template.expand(&Placeholders {
+			input,
+			selected: &selected,
+			cwd
+		});
+		assert_eq!(expected, expanded);
+	}
+}
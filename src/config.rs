@@ -0,0 +1,272 @@
+use std::{
+	collections::HashMap,
+	env,
+	path::{Path, PathBuf}
+};
+
+use anyhow::{anyhow, Context};
+use chin_tools::wrapper::anyhow::AResult;
+use serde::Deserialize;
+
+use crate::{
+	dirwalker::FindType,
+	keymap::{Action, BuiltinAction, KeyBind, Keymap},
+	ui::theme::Theme
+};
+
+/// `$XDG_CONFIG_HOME/ffp/config.toml`, every table and field is optional
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+	pub general: GeneralConfig,
+	pub theme: Theme,
+	pub preview: PreviewConfig,
+	/// key -> builtin action, `ignore` unbinds a default key
+	pub keys: HashMap<KeyBind, BuiltinAction>,
+	/// key -> command, `execute-silent(...)` runs it in the background
+	pub actions: HashMap<KeyBind, String>
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GeneralConfig {
+	pub mode: FindType,
+	pub hidden: bool,
+	pub ignore: bool,
+	/// Rank matches by score instead of keeping the walk order
+	pub sort: bool
+}
+
+impl Default for GeneralConfig {
+	fn default() -> Self {
+		Self {
+			mode: FindType::FIND,
+			hidden: false,
+			ignore: true,
+			sort: true
+		}
+	}
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PreviewConfig {
+	pub enabled: bool,
+	pub wrap: bool,
+	/// A line number gutter on text previews
+	pub line_numbers: bool,
+	/// Previews every file but directories with this instead of the builtin viewers
+	pub command: Option<String>,
+	/// Extension (`pdf`) or mime type (`application/pdf`, `image/*`) -> command, picked
+	/// before `command`. Directories are `inode/directory`
+	pub commands: HashMap<String, String>,
+	/// Milliseconds a preview command may run before it is killed
+	pub timeout: u64,
+	/// Syntax highlighting theme, picked by the terminal's background when unset. Themes in
+	/// `$XDG_CONFIG_HOME/ffp/themes/` are named by their file name
+	pub theme: Option<String>,
+	pub light_theme: String,
+	pub dark_theme: String
+}
+
+impl Default for PreviewConfig {
+	fn default() -> Self {
+		Self {
+			enabled: true,
+			wrap: false,
+			line_numbers: false,
+			command: None,
+			commands: HashMap::new(),
+			timeout: 3000,
+			theme: None,
+			light_theme: "base16-ocean.light".to_string(),
+			dark_theme: "base16-ocean.dark".to_string()
+		}
+	}
+}
+
+impl PreviewConfig {
+	/// The theme set explicitly, or the one matching a dark or light background
+	pub fn theme(&self, dark: bool) -> &str {
+		match self.theme.as_deref() {
+			Some(theme) => theme,
+			None if dark => &self.dark_theme,
+			None => &self.light_theme
+		}
+	}
+
+	/// Whether a command is keyed by a mime type, which has to be asked from libmagic
+	pub fn wants_mime(&self) -> bool {
+		self.commands.keys().any(|key| key.contains('/'))
+	}
+
+	/// The command previewing `path`, by its extension, then its mime type and then the
+	/// catch-all `command`
+	pub fn command_for(&self, path: &Path, is_dir: bool, mime: Option<&str>) -> Option<&str> {
+		let ext = path
+			.extension()
+			.filter(|_| !is_dir)
+			.map(|ext| ext.to_string_lossy().to_lowercase());
+		let by_ext = ext.and_then(|ext| {
+			self.commands
+				.iter()
+				.find(|(key, _)| key.trim_start_matches('.').eq_ignore_ascii_case(&ext))
+		});
+		let by_mime = || {
+			let mime = mime?;
+			let wildcard = format!("{}/*", mime.split('/').next()?);
+			self.commands
+				.get(mime)
+				.or_else(|| self.commands.get(&wildcard))
+		};
+
+		by_ext
+			.map(|(_, command)| command)
+			.or_else(by_mime)
+			.or(self.command.as_ref().filter(|_| !is_dir))
+			.map(String::as_str)
+	}
+}
+
+/// The directory holding config.toml and anything else the user drops in for ffp
+pub fn config_dir() -> Option<PathBuf> {
+	env::var_os("XDG_CONFIG_HOME")
+		.filter(|dir| !dir.is_empty())
+		.map(PathBuf::from)
+		.or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+		.map(|dir| dir.join("ffp"))
+}
+
+impl Config {
+	/// A missing file is the default config, a broken one is an error
+	pub fn load(path: Option<PathBuf>) -> AResult<Self> {
+		let explicit = path.is_some();
+		let Some(path) = path.or_else(|| config_dir().map(|dir| dir.join("config.toml"))) else {
+			return Ok(Default::default());
+		};
+
+		let content = match std::fs::read_to_string(&path) {
+			Ok(content) => content,
+			Err(err) if err.kind() == std::io::ErrorKind::NotFound && !explicit =>
+				return Ok(Default::default()),
+			Err(err) => return Err(err).with_context(|| format!("unable to read {}", path.display()))
+		};
+
+		Self::parse(&content).map_err(|err| anyhow!("invalid config {}: {}", path.display(), err))
+	}
+
+	pub fn parse(content: &str) -> AResult<Self> {
+		// toml's errors already carry the line, column and the offending key
+		Ok(toml::from_str(content)?)
+	}
+
+	pub fn keymap(&self) -> AResult<Keymap> {
+		let mut keymap = Keymap::default();
+		for (key, action) in self.keys.iter() {
+			keymap.bind(*key, Action::Builtin(*action));
+		}
+		for (key, command) in self.actions.iter() {
+			let action =
+				Action::command(command).map_err(|err| anyhow!("actions.\"{}\": {}", key, err))?;
+			keymap.bind(*key, action);
+		}
+		Ok(keymap)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parse_full_config() {
+		let config = Config::parse(
+			r##"
+			[general]
+			mode = "ls"
+			hidden = true
+
+			[theme]
+			selection_bg = "#282828"
+			marker_fg = "light-red"
+
+			[preview]
+			wrap = true
+			command = "bat --color=always {sel}"
+
+			[preview.commands]
+			pdf = "pdftotext {sel} -"
+			"image/*" = "mediainfo {sel}"
+
+			[keys]
+			"ctrl-j" = "down"
+			"enter" = "ignore"
+
+			[actions]
+			"ctrl-e" = "$EDITOR {sel}"
+			"ctrl-y" = "execute-silent(wl-copy {sel})"
+			"##
+		)
+		.unwrap();
+
+		assert_eq!(config.general.mode, FindType::LS);
+		assert!(config.general.hidden);
+		assert!(config.general.ignore);
+		assert!(config.preview.enabled);
+		assert!(config.preview.wrap);
+		assert_eq!(config.preview.commands.len(), 2);
+		assert_eq!(config.keys.len(), 2);
+		assert!(config.keymap().is_ok());
+	}
+
+	#[test]
+	fn preview_command_for() {
+		let config = Config::parse(
+			r##"
+			[preview]
+			command = "bat {sel}"
+
+			[preview.commands]
+			".PDF" = "pdftotext {sel} -"
+			"image/*" = "mediainfo {sel}"
+			"inode/directory" = "tree {sel}"
+			"##
+		)
+		.unwrap()
+		.preview;
+
+		let command = |path: &str, is_dir: bool, mime: Option<&str>| {
+			config.command_for(Path::new(path), is_dir, mime)
+		};
+		assert_eq!(Some("pdftotext {sel} -"), command("a.pdf", false, None));
+		assert_eq!(Some("mediainfo {sel}"), command("a.png", false, Some("image/png")));
+		assert_eq!(Some("bat {sel}"), command("a.rs", false, Some("text/x-rust")));
+		assert_eq!(Some("tree {sel}"), command("src", true, Some("inode/directory")));
+		assert_eq!(None, command("a.pdf", true, None));
+		assert!(config.wants_mime());
+	}
+
+	#[test]
+	fn preview_theme() {
+		let config = Config::parse("[preview]\nlight_theme = \"InspiredGitHub\"\n").unwrap();
+		assert_eq!("InspiredGitHub", config.preview.theme(false));
+		assert_eq!("base16-ocean.dark", config.preview.theme(true));
+
+		let config = Config::parse("[preview]\ntheme = \"Solarized (dark)\"\n").unwrap();
+		assert_eq!("Solarized (dark)", config.preview.theme(false));
+	}
+
+	#[test]
+	fn unknown_action_names_the_key() {
+		let err = Config::parse("[keys]\n\"ctrl-j\" = \"dwon\"\n").unwrap_err();
+		let msg = err.to_string();
+		assert!(msg.contains("line 2"), "{}", msg);
+		assert!(msg.contains("unknown action `dwon`"), "{}", msg);
+	}
+
+	#[test]
+	fn unknown_field_is_an_error() {
+		let err = Config::parse("[general]\nhiden = true\n").unwrap_err();
+		assert!(err.to_string().contains("hiden"), "{}", err);
+	}
+}
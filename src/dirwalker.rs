@@ -1,24 +1,55 @@
-use std::path::Path;
+use std::{
+	path::Path,
+	sync::atomic::{AtomicU64, Ordering},
+	time::Duration
+};
 
 use chin_tools::wrapper::anyhow::AResult;
 use flume::Sender;
-use futures_util::StreamExt;
-use tokio::{fs::File, io::AsyncReadExt};
+use ignore::WalkBuilder;
+use tokio::{
+	fs::File,
+	io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader}
+};
 use tracing::error;
 
 use crate::{fileinfo::FilePath, ui::finder::FinderIn};
 
-#[derive(Clone, Default)]
+/// Send what we have read so far if stdin is quiet for this long, so slow producers like
+/// `rg --files` on a big tree still show up progressively
+const STDIN_FLUSH_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Source {
+	#[default]
+	Walk,
+	Stdin
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum FindType {
 	LS,
 	#[default]
 	FIND
 }
 
-#[derive(Clone, Default)]
+#[derive(Clone)]
 pub struct DirFilter {
 	find_type: FindType,
-	dotfile: bool
+	dotfile: bool,
+	/// Honor .gitignore, .ignore, .git/info/exclude and the global git excludes
+	ignore: bool
+}
+
+impl Default for DirFilter {
+	fn default() -> Self {
+		Self {
+			find_type: Default::default(),
+			dotfile: false,
+			ignore: true
+		}
+	}
 }
 
 pub struct DirFilterBuilder {
@@ -31,6 +62,22 @@ impl DirFilter {
 			filter: Default::default()
 		}
 	}
+
+	pub fn into_builder(self) -> DirFilterBuilder {
+		DirFilterBuilder { filter: self }
+	}
+
+	pub fn ignore(&self) -> bool {
+		self.ignore
+	}
+
+	pub fn dotfile(&self) -> bool {
+		self.dotfile
+	}
+
+	pub fn find_type(&self) -> FindType {
+		self.find_type
+	}
 }
 
 impl DirFilterBuilder {
@@ -43,78 +90,198 @@ impl DirFilterBuilder {
 		}
 	}
 
+	pub fn with_dotfile(self, dotfile: bool) -> Self {
+		DirFilterBuilder {
+			filter: DirFilter {
+				dotfile,
+				..self.filter
+			}
+		}
+	}
+
+	pub fn with_ignore(self, ignore: bool) -> Self {
+		DirFilterBuilder {
+			filter: DirFilter {
+				ignore,
+				..self.filter
+			}
+		}
+	}
+
 	pub fn build(self) -> DirFilter {
 		self.filter
 	}
 }
 
+/// Bumped for every new walk, an older walk stops as soon as it notices
+static WALK_TICKET: AtomicU64 = AtomicU64::new(0);
+
 pub fn rebuild_dirlist_start(sender: Sender<FinderIn>, cwd: &str, filter: DirFilter) {
+	let ticket = WALK_TICKET.fetch_add(1, Ordering::Relaxed) + 1;
 	let cwd = cwd.to_string();
 	tokio::spawn(async move {
 		let cwd_ref = cwd.as_str();
-		walk_dir(sender, cwd_ref, filter).await
+		walk_dir(sender, cwd_ref, filter, ticket).await
 	});
 }
 
-pub async fn walk_dir(tx: Sender<FinderIn>, cwd: &str, filter: DirFilter) {
-	let mut items: Vec<FilePath> = Vec::with_capacity(50000);
+fn is_current_walk(ticket: u64) -> bool {
+	WALK_TICKET.load(Ordering::Relaxed) == ticket
+}
+
+pub async fn walk_dir(tx: Sender<FinderIn>, cwd: &str, filter: DirFilter, ticket: u64) {
+	if !is_current_walk(ticket) {
+		return;
+	}
 	if let Err(err) = tx.send_async(FinderIn::Clear).await {
 		tracing::error!("unable to send clear msg, {}", err);
 	}
-	match filter.find_type {
-		FindType::LS =>
-			if let Ok(mut dir) = tokio::fs::read_dir(cwd).await {
-				while let Ok(Some(en)) = dir.next_entry().await {
-					let path = en.path();
-					let info = FilePath::new(path, cwd);
-					items.push(info);
-					if items.len() > 50000 {
-						tx.send_async(FinderIn::ContentsExtend(items))
-							.await
-							.map_err(|err| error!("unable to send content extend msg: {}", err))
-							.ok();
-						items = Vec::with_capacity(50000);
-					}
-				}
-			},
-		FindType::FIND => {
-			let mut wd = async_walkdir::WalkDir::new(cwd);
-			while let Some(en) = wd.next().await {
-				match en {
-					Ok(de) => {
-						items.push(FilePath::new(de.path(), cwd));
-						if items.len() > 50000 {
-							tx.send_async(FinderIn::ContentsExtend(items))
-								.await
-								.map_err(|err| error!("unable to send content extend msg: {}", err))
-								.ok();
-							items = Vec::with_capacity(50000);
-						}
-					}
-					Err(err) => {
-						error!("unable to read file, err: {}", err)
+
+	let items = {
+		let tx = tx.clone();
+		let cwd = cwd.to_string();
+		// the ignore walker is blocking, keep it off the runtime threads
+		tokio::task::spawn_blocking(move || find_dir(&tx, &cwd, &filter, ticket))
+			.await
+			.map_err(|err| error!("unable to join walk task: {}", err))
+			.unwrap_or_default()
+	};
+
+	if !is_current_walk(ticket) {
+		return;
+	}
+	send_items(&tx, items).await;
+	send_finished(&tx).await;
+}
+
+/// Walks `cwd` recursively, or only its entries in [`FindType::LS`], both with the same
+/// ignore rules
+fn find_dir(tx: &Sender<FinderIn>, cwd: &str, filter: &DirFilter, ticket: u64) -> Vec<FilePath> {
+	let mut items: Vec<FilePath> = Vec::with_capacity(50000);
+
+	let mut builder = WalkBuilder::new(cwd);
+	builder
+		.standard_filters(filter.ignore)
+		// a .gitignore counts outside of a git checkout too
+		.require_git(false)
+		.hidden(!filter.dotfile);
+	if filter.find_type == FindType::LS {
+		builder.max_depth(Some(1));
+	}
+	if filter.ignore {
+		// .git is never listed in a .gitignore, but nobody wants to pick files from it
+		builder.filter_entry(|en| en.file_name() != ".git");
+	}
+
+	// depth 0 is `cwd` itself
+	for en in builder.build().filter(|en| en.as_ref().map_or(true, |en| en.depth() > 0)) {
+		match en {
+			Ok(de) => {
+				items.push(FilePath::new(de.into_path(), cwd));
+				if items.len() > 50000 {
+					if !is_current_walk(ticket) {
+						return vec![];
 					}
+					tx.send(FinderIn::ContentsExtend(std::mem::replace(
+						&mut items,
+						Vec::with_capacity(50000)
+					)))
+					.map_err(|err| error!("unable to send content extend msg: {}", err))
+					.ok();
 				}
 			}
+			Err(err) => {
+				error!("unable to read file, err: {}", err)
+			}
 		}
 	}
 
-	tx.send_async(FinderIn::ContentsExtend(items))
-		.await
-		.map_err(|err| error!("unable to send content extend msg: {}", err))
-		.ok();
+	items
 }
 
-pub async fn read_first_n_chars(path: &Path, n: usize) -> AResult<String> {
-	let mut file = File::open(path).await?;
+/// `ansi` parses the color codes of the lines instead of taking them as text
+pub fn read_stdin_start(sender: Sender<FinderIn>, cwd: &str, ansi: bool) {
+	let cwd = cwd.to_string();
+	tokio::spawn(async move {
+		let cwd_ref = cwd.as_str();
+		read_stdin(sender, cwd_ref, ansi).await
+	});
+}
 
-	let mut buffer = vec![0u8; n];
+pub async fn read_stdin(tx: Sender<FinderIn>, cwd: &str, ansi: bool) {
+	read_lines(tx, tokio::io::stdin(), cwd, ansi).await
+}
 
-	file.read_buf(&mut buffer).await?;
+/// Sends the lines of `input` in chunks as they come, the source is finished only once
+/// `input` is exhausted
+async fn read_lines(tx: Sender<FinderIn>, input: impl AsyncRead + Unpin, cwd: &str, ansi: bool) {
+	let mut lines: Vec<String> = Vec::with_capacity(50000);
+	let mut reader = BufReader::new(input);
+	// `read_until` keeps partially read bytes in `buf` when the timeout fires
+	let mut buf = Vec::new();
 
-	let result = String::from_utf8(buffer)?;
+	loop {
+		match tokio::time::timeout(STDIN_FLUSH_INTERVAL, reader.read_until(b'\n', &mut buf)).await {
+			Ok(Ok(0)) => break,
+			Ok(Ok(_)) => {
+				let line = String::from_utf8_lossy(&buf);
+				let line = line.trim_end_matches(['\n', '\r']);
+				if !line.is_empty() {
+					lines.push(line.to_string());
+				}
+				buf.clear();
 
-	Ok(result)
+				if lines.len() > 50000 {
+					let chunk = std::mem::replace(&mut lines, Vec::with_capacity(50000));
+					send_lines(&tx, chunk, cwd, ansi).await;
+				}
+			}
+			Ok(Err(err)) => {
+				error!("unable to read stdin, err: {}", err);
+				break;
+			}
+			Err(_) =>
+				if !lines.is_empty() {
+					send_lines(&tx, std::mem::take(&mut lines), cwd, ansi).await;
+				},
+		}
+	}
+
+	send_lines(&tx, lines, cwd, ansi).await;
+	send_finished(&tx).await;
+}
+
+/// Grep results are told apart from file names by looking at the files, so the lines are
+/// turned into candidates off the runtime threads
+async fn send_lines(tx: &Sender<FinderIn>, lines: Vec<String>, cwd: &str, ansi: bool) {
+	let cwd = cwd.to_string();
+	let items = tokio::task::spawn_blocking(move || {
+		lines
+			.into_iter()
+			.map(|line| match ansi {
+				true => FilePath::from_ansi_line(&line, &cwd),
+				false => FilePath::from_line(line, &cwd)
+			})
+			.collect::<Vec<_>>()
+	})
+	.await
+	.map_err(|err| error!("unable to join stdin parse task: {}", err))
+	.unwrap_or_default();
+	send_items(tx, items).await;
+}
+
+async fn send_finished(tx: &Sender<FinderIn>) {
+	tx.send_async(FinderIn::Finished)
+		.await
+		.map_err(|err| error!("unable to send finished msg: {}", err))
+		.ok();
+}
+
+async fn send_items(tx: &Sender<FinderIn>, items: Vec<FilePath>) {
+	tx.send_async(FinderIn::ContentsExtend(items))
+		.await
+		.map_err(|err| error!("unable to send content extend msg: {}", err))
+		.ok();
 }
 
 pub async fn file_is_text(path: &Path) -> AResult<bool> {
@@ -137,3 +304,36 @@ pub async fn file_is_text(path: &Path) -> AResult<bool> {
 
 	Ok(true)
 }
+
+#[cfg(test)]
+mod tests {
+	use tokio::io::AsyncWriteExt;
+
+	use super::*;
+
+	#[tokio::test]
+	async fn stdin_finishes_at_eof() {
+		let (tx, rx) = flume::unbounded();
+		let (mut writer, reader) = tokio::io::duplex(64);
+		let reading = tokio::spawn(async move { read_lines(tx, reader, "/tmp", false).await });
+
+		writer.write_all(b"a\nb\n").await.unwrap();
+		// the quiet input is flushed, but more may come
+		assert!(
+			matches!(rx.recv_async().await, Ok(FinderIn::ContentsExtend(items)) if items.len() == 2)
+		);
+		assert!(rx.is_empty());
+
+		writer.write_all(b"c\n").await.unwrap();
+		drop(writer);
+		reading.await.unwrap();
+		let rest = rx.drain().collect::<Vec<_>>();
+		assert!(matches!(rest.last(), Some(FinderIn::Finished)));
+		assert_eq!(
+			1,
+			rest.iter()
+				.filter(|msg| matches!(msg, FinderIn::Finished))
+				.count()
+		);
+	}
+}
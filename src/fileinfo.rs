@@ -1,15 +1,26 @@
 use std::{
+	borrow::Cow,
 	fs::Metadata,
-	path::{Path, PathBuf}
+	path::{Path, PathBuf},
+	sync::Arc
 };
 
+use ratatui::style::Style;
 use tracing::warn;
 
+use crate::ansi;
+
 #[derive(Clone, Debug)]
 pub struct FilePath {
 	pub pathbuf: PathBuf,
 	pathstr: String,
-	show_start: usize
+	show_start: usize,
+	/// Read from outside, printed exactly as given
+	verbatim: bool,
+	/// Where the styles of a line with ANSI colors start, as byte offsets into `pathstr`
+	styles: Option<Arc<[(usize, Style)]>>,
+	/// The 1-based line and column a grep result like `path:line:col:text` points at
+	position: Option<(usize, Option<usize>)>
 }
 
 impl Into<FileInfo> for FilePath {
@@ -30,8 +41,9 @@ pub struct FileInfo {
 }
 
 impl PartialEq for FilePath {
+	/// Two matches in the same file are different candidates
 	fn eq(&self, other: &Self) -> bool {
-		self.pathbuf == other.pathbuf
+		self.pathbuf == other.pathbuf && self.position == other.position
 	}
 }
 
@@ -41,6 +53,37 @@ impl PartialEq for FileInfo {
 	}
 }
 
+/// Splits `path:line`, `path:line:col` and either followed by `:text`, the way `grep -n` and
+/// `rg --vimgrep` print their results. File names like `12:30:00.log` are common too, so
+/// `exists` is asked whether the whole line is a file first and which split is one after
+fn split_position(
+	line: &str,
+	exists: impl Fn(&str) -> bool
+) -> Option<(&str, usize, Option<usize>)> {
+	let number = |s: &str| {
+		(!s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()))
+			.then(|| s.parse::<usize>().ok())
+			.flatten()
+			.filter(|n| *n > 0)
+	};
+
+	let mut splits = line
+		.match_indices(':')
+		.filter(|(i, _)| *i > 0)
+		.filter_map(|(i, _)| {
+			let mut fields = line[i + 1..].splitn(3, ':');
+			let row = fields.next().and_then(number)?;
+			Some((&line[..i], row, fields.next().and_then(number)))
+		})
+		.peekable();
+	// plain lines are never looked up
+	let first = *splits.peek()?;
+	if exists(line) {
+		return None;
+	}
+	splits.find(|(path, ..)| exists(path)).or(Some(first))
+}
+
 fn diff_path(base: &str, total: &str) -> usize {
 	if base == "/" {
 		return 0;
@@ -65,7 +108,48 @@ impl FilePath {
 		FilePath {
 			pathstr,
 			pathbuf,
-			show_start
+			show_start,
+			verbatim: false,
+			styles: None,
+			position: None
+		}
+	}
+
+	/// A candidate read from outside (e.g. stdin), shown exactly as given. It does not have to
+	/// be a path at all, relative ones are resolved against `base` for the preview. A grep
+	/// result previews its file at the line it points at, telling one apart stats files so
+	/// this blocks
+	pub fn from_line(line: String, base: &str) -> Self {
+		let exists = |path: &str| Path::new(base).join(path).exists();
+		let (path, position) = match split_position(&line, exists) {
+			Some((path, row, col)) => (path, Some((row, col))),
+			None => (line.as_str(), None)
+		};
+		FilePath {
+			pathbuf: Path::new(base).join(path),
+			pathstr: line,
+			show_start: 0,
+			verbatim: true,
+			styles: None,
+			position
+		}
+	}
+
+	/// Like [`FilePath::from_line`], the colors of `line` are kept apart so that neither the
+	/// matching nor the printed line sees the escape sequences
+	pub fn from_ansi_line(line: &str, base: &str) -> Self {
+		let parsed = ansi::parse_line(line, &mut Style::default());
+		let mut text = String::with_capacity(line.len());
+		let mut styles = Vec::with_capacity(parsed.spans.len());
+		for span in parsed.spans.iter() {
+			styles.push((text.len(), span.style));
+			text.push_str(&span.content);
+		}
+
+		let plain = styles.iter().all(|(_, style)| *style == Style::default());
+		FilePath {
+			styles: (!plain).then(|| styles.into()),
+			..Self::from_line(text, base)
 		}
 	}
 
@@ -73,9 +157,35 @@ impl FilePath {
 		&self.pathbuf
 	}
 
+	pub fn position(&self) -> Option<(usize, Option<usize>)> {
+		self.position
+	}
+
 	pub fn line(&self) -> &str {
 		&self.pathstr[self.show_start..]
 	}
+
+	/// The color of the byte at `offset` of [`FilePath::line`], `None` when it has none
+	pub fn style_at(&self, offset: usize) -> Option<Style> {
+		let styles = self.styles.as_ref()?;
+		let offset = offset + self.show_start;
+		let run = styles.partition_point(|(start, _)| *start <= offset).checked_sub(1)?;
+		Some(styles[run].1).filter(|style| *style != Style::default())
+	}
+
+	pub fn absolute(&self) -> Cow<'_, str> {
+		self.pathbuf.to_string_lossy()
+	}
+
+	/// Relative to `base` which may differ from the directory this was walked from
+	pub fn relative(&self, base: &Path) -> Cow<'_, str> {
+		if self.verbatim {
+			return self.line().into();
+		}
+
+		pathdiff::diff_paths(&self.pathbuf, base)
+			.map_or_else(|| self.line().into(), |rel| rel.to_string_lossy().to_string().into())
+	}
 }
 
 impl FileInfo {
@@ -83,3 +193,51 @@ impl FileInfo {
 		&self.path.path()
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use ratatui::style::Color;
+	use rstest::*;
+
+	use super::*;
+
+	const LOG: &str = "2024-01-01 12:30:00.log";
+
+	#[rstest]
+	#[case::vimgrep("src/a.rs:12:5:let x = 1;", &[], Some(("src/a.rs", 12, Some(5))))]
+	#[case::grep("src/a.rs:12:let x: u8 = 1;", &[], Some(("src/a.rs", 12, None)))]
+	#[case::line_only("src/a.rs:12", &[], Some(("src/a.rs", 12, None)))]
+	#[case::colon_in_path("a:b.rs:3:x", &[], Some(("a:b.rs", 3, None)))]
+	#[case::plain("src/a.rs", &[], None)]
+	#[case::no_number("notes: todo", &[], None)]
+	#[case::zero("a.rs:0:x", &[], None)]
+	#[case::file_named_like_one("foo:12", &["foo:12"], None)]
+	#[case::timestamp_file(LOG, &[LOG], None)]
+	#[case::grep_in_timestamp_file("2024-01-01 12:30:00.log:5:x", &[LOG], Some((LOG, 5, None)))]
+	fn position(
+		#[case] line: &str,
+		#[case] files: &[&str],
+		#[case] expected: Option<(&str, usize, Option<usize>)>
+	) {
+		assert_eq!(expected, split_position(line, |path| files.contains(&path)));
+	}
+
+	#[test]
+	fn ansi_line() {
+		let path = FilePath::from_ansi_line(
+			"\x1b[35msrc/main.rs\x1b[0m:\x1b[32m12\x1b[0m:fn main",
+			"/tmp"
+		);
+		assert_eq!("src/main.rs:12:fn main", path.line());
+		assert_eq!(Path::new("/tmp/src/main.rs"), path.path());
+		assert_eq!(Some((12, None)), path.position());
+
+		let purple = Style::new().fg(Color::Indexed(5));
+		assert_eq!(Some(purple), path.style_at(0));
+		assert_eq!(Some(purple), path.style_at(10));
+		assert_eq!(None, path.style_at(11));
+		assert_eq!(Some(Style::new().fg(Color::Indexed(2))), path.style_at(13));
+
+		assert!(FilePath::from_ansi_line("plain", "/tmp").styles.is_none());
+	}
+}
@@ -0,0 +1,404 @@
+use std::{collections::HashMap, fmt::Display, str::FromStr};
+
+use anyhow::{anyhow, bail};
+use chin_tools::wrapper::anyhow::AResult;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use serde::Deserialize;
+
+use crate::command::{CommandTemplate, ExecMode};
+
+/// A key with its modifiers, written like `ctrl-e`, `alt-.` or `shift-tab`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub struct KeyBind {
+	pub code: KeyCode,
+	pub modifiers: KeyModifiers
+}
+
+impl KeyBind {
+	fn new(code: KeyCode, modifiers: KeyModifiers) -> Self {
+		// shift is already part of an uppercase char or of BackTab
+		let modifiers = match code {
+			KeyCode::Char(_) | KeyCode::BackTab => modifiers - KeyModifiers::SHIFT,
+			_ => modifiers
+		};
+		Self { code, modifiers }
+	}
+}
+
+impl From<KeyEvent> for KeyBind {
+	fn from(key: KeyEvent) -> Self {
+		KeyBind::new(key.code, key.modifiers)
+	}
+}
+
+impl FromStr for KeyBind {
+	type Err = anyhow::Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let mut modifiers = KeyModifiers::NONE;
+		let mut rest = s;
+		// `-` alone or as the last part (`alt--`) is the key itself
+		while let Some((modifier, key)) = rest.split_once('-').filter(|(_, key)| !key.is_empty()) {
+			modifiers |= match modifier.to_lowercase().as_str() {
+				"ctrl" | "c" => KeyModifiers::CONTROL,
+				"alt" | "a" | "m" => KeyModifiers::ALT,
+				"shift" | "s" => KeyModifiers::SHIFT,
+				_ => bail!("unknown modifier `{}` in key `{}`", modifier, s)
+			};
+			rest = key;
+		}
+
+		let code = match rest.to_lowercase().as_str() {
+			"enter" | "return" => KeyCode::Enter,
+			"esc" | "escape" => KeyCode::Esc,
+			"tab" if modifiers.contains(KeyModifiers::SHIFT) => KeyCode::BackTab,
+			"tab" => KeyCode::Tab,
+			"btab" | "backtab" => KeyCode::BackTab,
+			"space" => KeyCode::Char(' '),
+			"bspace" | "backspace" => KeyCode::Backspace,
+			"del" | "delete" => KeyCode::Delete,
+			"ins" | "insert" => KeyCode::Insert,
+			"up" => KeyCode::Up,
+			"down" => KeyCode::Down,
+			"left" => KeyCode::Left,
+			"right" => KeyCode::Right,
+			"home" => KeyCode::Home,
+			"end" => KeyCode::End,
+			"pgup" | "pageup" => KeyCode::PageUp,
+			"pgdn" | "pagedown" => KeyCode::PageDown,
+			f if f.len() > 1 && f.starts_with('f') && f[1..].parse::<u8>().is_ok() =>
+				KeyCode::F(f[1..].parse().unwrap()),
+			_ => {
+				let mut chars = rest.chars();
+				match (chars.next(), chars.next()) {
+					(Some(c), None) => KeyCode::Char(c),
+					_ => bail!("unknown key `{}`", s)
+				}
+			}
+		};
+
+		Ok(KeyBind::new(code, modifiers))
+	}
+}
+
+impl TryFrom<String> for KeyBind {
+	type Error = anyhow::Error;
+
+	fn try_from(value: String) -> Result<Self, Self::Error> {
+		value.parse()
+	}
+}
+
+impl Display for KeyBind {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		if self.modifiers.contains(KeyModifiers::CONTROL) {
+			write!(f, "ctrl-")?;
+		}
+		if self.modifiers.contains(KeyModifiers::ALT) {
+			write!(f, "alt-")?;
+		}
+		if self.modifiers.contains(KeyModifiers::SHIFT) {
+			write!(f, "shift-")?;
+		}
+		match self.code {
+			KeyCode::Char(' ') => write!(f, "space"),
+			KeyCode::Char(c) => write!(f, "{}", c),
+			KeyCode::F(n) => write!(f, "f{}", n),
+			KeyCode::BackTab => write!(f, "btab"),
+			code => write!(f, "{:?}", code)
+		}
+	}
+}
+
+/// Everything ffp can do on a key besides running commands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BuiltinAction {
+	Accept,
+	Abort,
+	Up,
+	Down,
+	ToggleDown,
+	ToggleUp,
+	SelectAll,
+	DeselectAll,
+	ToggleHidden,
+	ToggleIgnore,
+	ToggleMode,
+	/// Rank matches by score or keep the source order
+	ToggleSort,
+	/// Match against the whole path, the file name or the directory
+	CycleScope,
+	EnterDir,
+	ParentDir,
+	BackwardChar,
+	ForwardChar,
+	BeginningOfLine,
+	EndOfLine,
+	BackwardDeleteChar,
+	PreviewUp,
+	PreviewDown,
+	PreviewPageUp,
+	PreviewPageDown,
+	PreviewTop,
+	PreviewBottom,
+	PreviewLeft,
+	PreviewRight,
+	PreviewLineNumbers,
+	/// Unbinds the key
+	Ignore
+}
+
+const BUILTIN_NAMES: &[(&str, BuiltinAction)] = &[
+	("accept", BuiltinAction::Accept),
+	("abort", BuiltinAction::Abort),
+	("up", BuiltinAction::Up),
+	("down", BuiltinAction::Down),
+	("toggle-down", BuiltinAction::ToggleDown),
+	("toggle-up", BuiltinAction::ToggleUp),
+	("select-all", BuiltinAction::SelectAll),
+	("deselect-all", BuiltinAction::DeselectAll),
+	("toggle-hidden", BuiltinAction::ToggleHidden),
+	("toggle-ignore", BuiltinAction::ToggleIgnore),
+	("toggle-mode", BuiltinAction::ToggleMode),
+	("toggle-sort", BuiltinAction::ToggleSort),
+	("cycle-scope", BuiltinAction::CycleScope),
+	("enter-dir", BuiltinAction::EnterDir),
+	("parent-dir", BuiltinAction::ParentDir),
+	("backward-char", BuiltinAction::BackwardChar),
+	("forward-char", BuiltinAction::ForwardChar),
+	("beginning-of-line", BuiltinAction::BeginningOfLine),
+	("end-of-line", BuiltinAction::EndOfLine),
+	("backward-delete-char", BuiltinAction::BackwardDeleteChar),
+	("preview-up", BuiltinAction::PreviewUp),
+	("preview-down", BuiltinAction::PreviewDown),
+	("preview-page-up", BuiltinAction::PreviewPageUp),
+	("preview-page-down", BuiltinAction::PreviewPageDown),
+	("preview-top", BuiltinAction::PreviewTop),
+	("preview-bottom", BuiltinAction::PreviewBottom),
+	("preview-left", BuiltinAction::PreviewLeft),
+	("preview-right", BuiltinAction::PreviewRight),
+	("preview-line-numbers", BuiltinAction::PreviewLineNumbers),
+	("ignore", BuiltinAction::Ignore)
+];
+
+const DEFAULT_BINDS: &[(&str, BuiltinAction)] = &[
+	("enter", BuiltinAction::Accept),
+	("esc", BuiltinAction::Abort),
+	("ctrl-c", BuiltinAction::Abort),
+	("up", BuiltinAction::Up),
+	("down", BuiltinAction::Down),
+	("tab", BuiltinAction::ToggleDown),
+	("btab", BuiltinAction::ToggleUp),
+	("ctrl-a", BuiltinAction::SelectAll),
+	("ctrl-d", BuiltinAction::DeselectAll),
+	("alt-.", BuiltinAction::ToggleHidden),
+	("alt-i", BuiltinAction::ToggleIgnore),
+	("ctrl-t", BuiltinAction::ToggleMode),
+	("ctrl-s", BuiltinAction::ToggleSort),
+	("alt-s", BuiltinAction::CycleScope),
+	("alt-l", BuiltinAction::EnterDir),
+	("alt-right", BuiltinAction::EnterDir),
+	("alt-h", BuiltinAction::ParentDir),
+	("alt-left", BuiltinAction::ParentDir),
+	("left", BuiltinAction::BackwardChar),
+	("right", BuiltinAction::ForwardChar),
+	("home", BuiltinAction::BeginningOfLine),
+	("end", BuiltinAction::EndOfLine),
+	("bspace", BuiltinAction::BackwardDeleteChar),
+	("shift-up", BuiltinAction::PreviewUp),
+	("shift-down", BuiltinAction::PreviewDown),
+	("pgup", BuiltinAction::PreviewPageUp),
+	("pgdn", BuiltinAction::PreviewPageDown),
+	("ctrl-home", BuiltinAction::PreviewTop),
+	("ctrl-end", BuiltinAction::PreviewBottom),
+	("shift-left", BuiltinAction::PreviewLeft),
+	("shift-right", BuiltinAction::PreviewRight),
+	("alt-n", BuiltinAction::PreviewLineNumbers)
+];
+
+impl FromStr for BuiltinAction {
+	type Err = anyhow::Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		BUILTIN_NAMES
+			.iter()
+			.find(|(name, _)| *name == s)
+			.map(|(_, action)| *action)
+			.ok_or_else(|| {
+				anyhow!(
+					"unknown action `{}`, expected one of {}",
+					s,
+					BUILTIN_NAMES
+						.iter()
+						.map(|(name, _)| *name)
+						.collect::<Vec<_>>()
+						.join(", ")
+				)
+			})
+	}
+}
+
+impl<'de> Deserialize<'de> for BuiltinAction {
+	fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		String::deserialize(deserializer)?
+			.parse()
+			.map_err(serde::de::Error::custom)
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+	Builtin(BuiltinAction),
+	Execute(CommandTemplate)
+}
+
+impl Action {
+	/// `execute(cmd)` runs in the foreground, `execute-silent(cmd)` in the background and a
+	/// bare `cmd` is the same as `execute(cmd)`
+	pub fn command(s: &str) -> AResult<Self> {
+		let wrapped = |name: &str| {
+			s.strip_prefix(name)
+				.and_then(|rest| rest.strip_prefix('('))
+				.and_then(|rest| rest.strip_suffix(')'))
+		};
+
+		let (template, mode) = if let Some(cmd) = wrapped("execute-silent") {
+			(cmd, ExecMode::Background)
+		} else if let Some(cmd) = wrapped("execute") {
+			(cmd, ExecMode::Foreground)
+		} else {
+			(s, ExecMode::Foreground)
+		};
+
+		if template.trim().is_empty() {
+			bail!("empty command in `{}`", s);
+		}
+
+		Ok(Action::Execute(CommandTemplate::new(template, mode)))
+	}
+}
+
+impl FromStr for Action {
+	type Err = anyhow::Error;
+
+	/// A builtin action name, otherwise a command
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.parse::<BuiltinAction>() {
+			Ok(action) => Ok(Action::Builtin(action)),
+			Err(_) => Action::command(s)
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct Keymap {
+	binds: HashMap<KeyBind, Action>
+}
+
+impl Default for Keymap {
+	fn default() -> Self {
+		let binds = DEFAULT_BINDS
+			.iter()
+			.map(|(key, action)| (key.parse().unwrap(), Action::Builtin(*action)))
+			.collect();
+		Self { binds }
+	}
+}
+
+impl Keymap {
+	pub fn bind(&mut self, key: KeyBind, action: Action) {
+		if action == Action::Builtin(BuiltinAction::Ignore) {
+			self.binds.remove(&key);
+		} else {
+			self.binds.insert(key, action);
+		}
+	}
+
+	/// Parses a `key:action` pair like `ctrl-e:execute($EDITOR {sel})`
+	pub fn bind_str(&mut self, spec: &str) -> AResult<()> {
+		// the key may be `:` itself, so look for the separator after the first char
+		let split = spec
+			.char_indices()
+			.skip(1)
+			.find(|(_, c)| *c == ':')
+			.map(|(idx, _)| idx)
+			.ok_or_else(|| anyhow!("expected `key:action`, got `{}`", spec))?;
+		let key: KeyBind = spec[..split].parse()?;
+		let action: Action = spec[split + 1..].parse()?;
+		self.bind(key, action);
+		Ok(())
+	}
+
+	pub fn get(&self, key: KeyEvent) -> Option<&Action> {
+		self.binds.get(&key.into())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use rstest::*;
+
+	use super::*;
+
+	#[rstest]
+	#[case::plain("a", KeyCode::Char('a'), KeyModifiers::NONE)]
+	#[case::ctrl("ctrl-e", KeyCode::Char('e'), KeyModifiers::CONTROL)]
+	#[case::alt_dot("alt-.", KeyCode::Char('.'), KeyModifiers::ALT)]
+	#[case::alt_dash("alt--", KeyCode::Char('-'), KeyModifiers::ALT)]
+	#[case::ctrl_alt("ctrl-alt-x", KeyCode::Char('x'), KeyModifiers::CONTROL | KeyModifiers::ALT)]
+	#[case::shift_tab("shift-tab", KeyCode::BackTab, KeyModifiers::NONE)]
+	#[case::named("pgdn", KeyCode::PageDown, KeyModifiers::NONE)]
+	#[case::shift_named("shift-up", KeyCode::Up, KeyModifiers::SHIFT)]
+	#[case::function("f12", KeyCode::F(12), KeyModifiers::NONE)]
+	fn parse_key(#[case] input: &str, #[case] code: KeyCode, #[case] modifiers: KeyModifiers) {
+		assert_eq!(KeyBind { code, modifiers }, input.parse::<KeyBind>().unwrap());
+	}
+
+	#[rstest]
+	#[case::unknown_modifier("hyper-x")]
+	#[case::unknown_key("ctrl-foo")]
+	#[case::empty("")]
+	fn parse_key_err(#[case] input: &str) {
+		assert!(input.parse::<KeyBind>().is_err());
+	}
+
+	#[test]
+	fn key_event_ignores_shift_on_chars() {
+		let event = KeyEvent::new(KeyCode::Char('E'), KeyModifiers::SHIFT);
+		assert_eq!(
+			KeyBind::from(event),
+			KeyBind::new(KeyCode::Char('E'), KeyModifiers::NONE)
+		);
+	}
+
+	#[test]
+	fn default_binds_parse() {
+		let keymap = Keymap::default();
+		assert_eq!(keymap.binds.len(), DEFAULT_BINDS.len());
+	}
+
+	#[rstest]
+	#[case::builtin("ctrl-j:down", Some(Action::Builtin(BuiltinAction::Down)))]
+	#[case::unbind("enter:ignore", None)]
+	fn bind_builtin(#[case] spec: &str, #[case] expected: Option<Action>) {
+		let mut keymap = Keymap::default();
+		keymap.bind_str(spec).unwrap();
+		let key = spec.split_once(':').unwrap().0.parse::<KeyBind>().unwrap();
+		assert_eq!(keymap.binds.get(&key), expected.as_ref());
+	}
+
+	#[rstest]
+	#[case::bare("ctrl-e:$EDITOR {sel}", "$EDITOR {sel}", ExecMode::Foreground)]
+	#[case::execute("ctrl-e:execute(vim {sel})", "vim {sel}", ExecMode::Foreground)]
+	#[case::silent("ctrl-y:execute-silent(wl-copy {sel})", "wl-copy {sel}", ExecMode::Background)]
+	fn bind_command(#[case] spec: &str, #[case] template: &str, #[case] mode: ExecMode) {
+		let mut keymap = Keymap::default();
+		keymap.bind_str(spec).unwrap();
+		let key = spec.split_once(':').unwrap().0.parse::<KeyBind>().unwrap();
+		assert_eq!(
+			keymap.binds.get(&key),
+			Some(&Action::Execute(CommandTemplate::new(template, mode)))
+		);
+	}
+}
@@ -1,16 +1,40 @@
-use std::sync::Arc;
-
-use dirwalker::FindType;
+use config::PreviewConfig;
+use dirwalker::{FindType, Source};
+use keymap::Keymap;
+use output::OutputFormat;
+use ui::theme::Theme;
 
+pub mod ansi;
+pub mod cli;
+pub mod command;
 pub mod componment;
+pub mod config;
 pub mod constant;
 pub mod dirwalker;
 pub mod fileinfo;
+pub mod keymap;
+pub mod matcher;
+pub mod output;
 pub mod tui;
 pub mod ui;
 pub mod vendor;
 
 pub struct AppState {
-	pub option: Arc<String>,
-	pub show_mode: FindType
+	pub cwd: String,
+	pub query: String,
+	pub source: Source,
+	/// Candidates from stdin carry ANSI colors
+	pub ansi: bool,
+	pub show_mode: FindType,
+	pub show_hidden: bool,
+	pub ignore: bool,
+	pub sort: bool,
+	pub theme: Theme,
+	pub preview: PreviewConfig,
+	pub output: OutputFormat,
+	/// Accept the only match once everything is loaded
+	pub select_1: bool,
+	/// Exit when nothing matches once everything is loaded
+	pub exit_0: bool,
+	pub keymap: Keymap
 }
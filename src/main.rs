@@ -1,64 +1,96 @@
 #![feature(if_let_guard)]
 
-use std::env;
+use std::path::Path;
 
+use clap::Parser;
 use crossterm::{
+	event::{DisableMouseCapture, EnableMouseCapture},
 	execute,
 	terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen}
 };
-use ffp::tui::Tui;
+use ffp::{
+	cli::Cli,
+	output,
+	tui::{Tui, TuiExit},
+	ui::preview::text::HighlightAssets
+};
 use ratatui::{backend::CrosstermBackend, Terminal};
 use ratatui_image::picker::Picker;
+use terminal_colorsaurus::{ColorScheme, QueryOptions};
 use tracing::error;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+	// parse before touching the terminal so --help and errors print normally
+	let mut state = Cli::parse().into_state()?;
+	let output_format = state.output;
+	let start_dir = state.cwd.clone();
+
 	let file_appender = tracing_appender::rolling::daily("/tmp/", "ffp.log");
 	let (non_blocking, _guard) = tracing_appender::non_blocking(file_appender);
 	tracing_subscriber::fmt().with_writer(non_blocking).init();
 
+	if state.preview.enabled {
+		HighlightAssets::preload();
+
+		// asks the terminal, so before the tui starts reading input like the picker below
+		if state.preview.theme.is_none() {
+			let dark = terminal_colorsaurus::color_scheme(QueryOptions::default())
+				.map_or(true, |scheme| scheme == ColorScheme::Dark);
+			state.preview.theme = Some(state.preview.theme(dark).to_string());
+		}
+		// a typo is reported here instead of falling back to the default silently
+		HighlightAssets::check_theme(state.preview.theme(true))?;
+	}
+
 	let window_size = chin_tools::utils::termutils::get_window_size_px()?;
 
-	// We need to create `picker` on this thread because if we create it on the `renderer` thread,
-	// it messes up something with user input. Input never makes it to the crossterm thing
+	// The picker queries the terminal, so create it before the tui starts reading input.
+	// Without graphics support it falls back to halfblocks
 	let mut picker = Picker::new((
 		window_size.width / window_size.columns,
 		window_size.height / window_size.rows
 	));
 	picker.guess_protocol();
 
-	// then we want to spawn off the rendering task
-	// We need to use the thread::spawn API so that this exists in a thread not owned by tokio,
-	// since the methods we call in `start_rendering` will panic if called in an async context
-	std::thread::spawn(move || {
-		// renderer::start_rendering(file_path, render_tx, render_rx, window_size)
-	});
-
-	let backend = CrosstermBackend::new(std::io::stdout());
+	// Draw on stderr so stdout stays clean for the accepted paths, e.g. `vim $(ffp)`
+	let backend = CrosstermBackend::new(std::io::stderr());
 	let mut term = Terminal::new(backend)?;
 
 	execute!(
 		term.backend_mut(),
 		EnterAlternateScreen,
+		EnableMouseCapture,
 		crossterm::cursor::Hide
 	)?;
 	enable_raw_mode()?;
 
-	let mut tui = Tui::new(env::current_dir()?.to_string_lossy().to_string().as_str());
+	let mut tui = Tui::new(state, picker);
 
-	match tui.run(&mut term).await {
-		Ok(_) => {}
-		Err(err) => {
-			error!("Some error occurs handling the tui event: {}", err);
-		}
-	}
+	let exit = tui.run(&mut term).await;
 
 	execute!(
 		term.backend_mut(),
 		LeaveAlternateScreen,
+		DisableMouseCapture,
 		crossterm::cursor::Show
 	)?;
 	disable_raw_mode()?;
 
-	Ok(())
+	let code = match exit {
+		Ok(exit) => {
+			if let TuiExit::Accept(paths) = &exit {
+				output::print_paths(paths, output_format, Path::new(&start_dir))?;
+			}
+			exit.code()
+		}
+		Err(err) => {
+			error!("Some error occurs handling the tui event: {}", err);
+			2
+		}
+	};
+
+	// `process::exit` skips destructors, so flush the log writer by hand
+	drop(_guard);
+	std::process::exit(code)
 }
@@ -0,0 +1,361 @@
+use nucleo_matcher::{
+	pattern::{Atom, AtomKind, CaseMatching, Normalization},
+	Config, Matcher, Utf32Str
+};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// A matcher tuned for paths, each thread needs its own
+pub fn new_matcher() -> Matcher {
+	Matcher::new(Config::DEFAULT.match_paths())
+}
+
+/// Which part of a path a query is matched against
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Scope {
+	/// The whole path, matches within the file name rank first
+	#[default]
+	Path,
+	Name,
+	Dir
+}
+
+impl Scope {
+	pub fn next(self) -> Self {
+		match self {
+			Scope::Path => Scope::Name,
+			Scope::Name => Scope::Dir,
+			Scope::Dir => Scope::Path
+		}
+	}
+
+	/// The part of `line` this scope matches and its offset in graphemes, which is what
+	/// match indices count. A trailing `/` belongs to the name
+	pub fn split(self, line: &str) -> (u32, &str) {
+		let name_start = line
+			.trim_end_matches('/')
+			.rfind('/')
+			.map_or(0, |i| i + 1);
+		match self {
+			Scope::Path => (0, line),
+			Scope::Name => (
+				line[..name_start].graphemes(true).count() as u32,
+				&line[name_start..]
+			),
+			Scope::Dir => (0, &line[..name_start])
+		}
+	}
+}
+
+/// A query in fzf's extended syntax. Every space separated term has to match and `a | b`
+/// matches either of them. `'exact`, `^prefix`, `suffix$` and `!negated` terms are parsed
+/// by nucleo, case is smart and unicode is normalized
+#[derive(Debug, Clone, Default)]
+pub struct Query {
+	/// All the groups have to match, any atom of a group is enough
+	groups: Vec<Vec<Atom>>,
+	/// What the atoms were parsed from, to compare queries
+	terms: Vec<Vec<Term>>
+}
+
+/// A term's syntax, parsed the way [`Atom::parse`] does it
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Term {
+	negative: bool,
+	kind: AtomKind,
+	text: String
+}
+
+/// Smart case and normalization turn strict once the needle has uppercase or non ascii chars
+fn is_strict(text: &str) -> bool {
+	text.chars().any(|c| c.is_uppercase() || !c.is_ascii())
+}
+
+fn is_subsequence(needle: &str, haystack: &str) -> bool {
+	let mut haystack = haystack.chars();
+	needle.chars().all(|c| haystack.any(|h| h == c))
+}
+
+impl Term {
+	fn parse(raw: &str) -> Self {
+		let mut text = raw;
+		let negative = text.starts_with('!');
+		if negative {
+			text = &text[1..];
+		}
+
+		let mut kind = match text.as_bytes() {
+			[b'^', ..] => {
+				text = &text[1..];
+				AtomKind::Prefix
+			}
+			[b'\'', ..] => {
+				text = &text[1..];
+				AtomKind::Substring
+			}
+			[b'\\', b'^' | b'\'', ..] => {
+				text = &text[1..];
+				AtomKind::Fuzzy
+			}
+			_ => AtomKind::Fuzzy
+		};
+
+		let mut text = match text.as_bytes() {
+			[.., b'\\', b'$'] => format!("{}$", &text[..text.len() - 2]),
+			[.., b'$'] => {
+				kind = if kind == AtomKind::Fuzzy {
+					AtomKind::Postfix
+				} else {
+					AtomKind::Exact
+				};
+				text[..text.len() - 1].to_string()
+			}
+			_ => text.to_string()
+		};
+
+		// a negated fuzzy term would exclude nearly everything
+		if negative && kind == AtomKind::Fuzzy {
+			kind = AtomKind::Substring;
+		}
+		if !is_strict(&text) {
+			text = text.to_lowercase();
+		}
+
+		Self {
+			negative,
+			kind,
+			text
+		}
+	}
+
+	/// Whatever `self` matches, `old` matches too
+	fn narrows(&self, old: &Term) -> bool {
+		use AtomKind::*;
+
+		if self.negative != old.negative {
+			return false;
+		}
+
+		if !self.negative {
+			// a case insensitive `old` accepts any case of the new text
+			let new = if is_strict(&old.text) {
+				self.text.clone()
+			} else {
+				self.text.to_lowercase()
+			};
+			match (self.kind, old.kind) {
+				// every kind matches its text in order, a fuzzy one in no other way
+				(_, Fuzzy) => is_subsequence(&old.text, &new),
+				(Substring | Prefix | Postfix | Exact, Substring) => new.contains(&old.text),
+				(Prefix | Exact, Prefix) => new.starts_with(&old.text),
+				(Postfix | Exact, Postfix) => new.ends_with(&old.text),
+				(Exact, Exact) => new == old.text,
+				_ => false
+			}
+		} else {
+			// excluding more, with a needle at most as strict as the old one
+			if is_strict(&self.text) && !is_strict(&old.text) {
+				return false;
+			}
+			match (self.kind, old.kind) {
+				(Substring, _) => old.text.contains(&self.text),
+				(Prefix, Prefix | Exact) => old.text.starts_with(&self.text),
+				(Postfix, Postfix | Exact) => old.text.ends_with(&self.text),
+				(Exact, Exact) => old.text == self.text,
+				_ => false
+			}
+		}
+	}
+}
+
+/// Splits on whitespace that isn't escaped with a backslash, `\ ` is a literal space
+fn terms(query: &str) -> Vec<String> {
+	let mut terms = vec![];
+	let mut term = String::new();
+	let mut chars = query.chars().peekable();
+	while let Some(c) = chars.next() {
+		match c {
+			'\\' if chars.peek().map_or(false, |c| c.is_whitespace()) => {
+				term.push(chars.next().unwrap());
+			}
+			c if c.is_whitespace() => {
+				if !term.is_empty() {
+					terms.push(std::mem::take(&mut term));
+				}
+			}
+			c => term.push(c)
+		}
+	}
+	if !term.is_empty() {
+		terms.push(term);
+	}
+	terms
+}
+
+impl Query {
+	pub fn parse(query: &str) -> Self {
+		let mut groups: Vec<Vec<Atom>> = vec![];
+		let mut terms: Vec<Vec<Term>> = vec![];
+		let mut or_next = false;
+		for raw in self::terms(query) {
+			if raw == "|" {
+				or_next = !groups.is_empty();
+				continue;
+			}
+
+			let atom = Atom::parse(&raw, CaseMatching::Smart, Normalization::Smart);
+			let term = Term::parse(&raw);
+			match (groups.last_mut(), terms.last_mut()) {
+				(Some(group), Some(group_terms)) if or_next => {
+					group.push(atom);
+					group_terms.push(term);
+				}
+				_ => {
+					groups.push(vec![atom]);
+					terms.push(vec![term]);
+				}
+			}
+			or_next = false;
+		}
+
+		Self { groups, terms }
+	}
+
+	/// Whether everything this query matches is also matched by `old`, so only the matches of
+	/// `old` need to be looked at. Conservative, `false` when in doubt
+	pub fn narrows(&self, old: &Query) -> bool {
+		old.terms.iter().all(|old_group| {
+			// one of our groups has to imply the old group, every alternative of it narrows
+			// some old alternative
+			self.terms.iter().any(|group| {
+				group
+					.iter()
+					.all(|term| old_group.iter().any(|old_term| term.narrows(old_term)))
+			})
+		})
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.groups.is_empty()
+	}
+
+	/// The sum of the best score in every group, `None` if a group doesn't match
+	pub fn score(&self, haystack: &str, matcher: &mut Matcher, buf: &mut Vec<char>) -> Option<u32> {
+		let haystack = Utf32Str::new(haystack, buf);
+		self.groups.iter().try_fold(0u32, |total, group| {
+			group
+				.iter()
+				.filter_map(|atom| atom.score(haystack, matcher))
+				.max()
+				.map(|score| total + score as u32)
+		})
+	}
+
+	/// Like [`Query::score`], also collecting the matched grapheme positions into `indices`,
+	/// sorted and without duplicates
+	pub fn indices(
+		&self,
+		haystack: &str,
+		matcher: &mut Matcher,
+		buf: &mut Vec<char>,
+		indices: &mut Vec<u32>
+	) -> Option<u32> {
+		let haystack = Utf32Str::new(haystack, buf);
+		let mut total = 0u32;
+		let mut group_indices = vec![];
+		for group in self.groups.iter() {
+			let mut best: Option<(u16, Vec<u32>)> = None;
+			for atom in group.iter() {
+				group_indices.clear();
+				if let Some(score) = atom.indices(haystack, matcher, &mut group_indices) {
+					if best.as_ref().map_or(true, |(best, _)| score > *best) {
+						best = Some((score, group_indices.clone()));
+					}
+				}
+			}
+			let (score, best_indices) = best?;
+			total += score as u32;
+			indices.extend(best_indices);
+		}
+
+		indices.sort_unstable();
+		indices.dedup();
+		Some(total)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use rstest::*;
+
+	use super::*;
+
+	#[rstest]
+	#[case::fuzzy("fnd", "src/ui/finder.rs", true)]
+	#[case::and("ui rs", "src/ui/finder.rs", true)]
+	#[case::and_miss("ui toml", "src/ui/finder.rs", false)]
+	#[case::or("toml | rs$", "src/ui/finder.rs", true)]
+	#[case::or_miss("toml | lock", "src/ui/finder.rs", false)]
+	#[case::exact("'der.r", "src/ui/finder.rs", true)]
+	#[case::exact_miss("'fnd", "src/ui/finder.rs", false)]
+	#[case::prefix("^src", "src/ui/finder.rs", true)]
+	#[case::prefix_miss("^ui", "src/ui/finder.rs", false)]
+	#[case::suffix("rs$", "src/ui/finder.rs", true)]
+	#[case::negation("src !test", "src/ui/finder.rs", true)]
+	#[case::negation_miss("!finder", "src/ui/finder.rs", false)]
+	#[case::smart_case("Finder", "src/ui/finder.rs", false)]
+	#[case::escaped_space("'a\\ b", "x/a b.txt", true)]
+	fn matches(#[case] query: &str, #[case] haystack: &str, #[case] expected: bool) {
+		let mut matcher = new_matcher();
+		let mut buf = vec![];
+		let score = Query::parse(query).score(haystack, &mut matcher, &mut buf);
+		assert_eq!(expected, score.is_some(), "{} ~ {}", query, haystack);
+	}
+
+	#[test]
+	fn indices_of_the_best_alternative() {
+		let mut matcher = new_matcher();
+		let mut buf = vec![];
+		let mut indices = vec![];
+		Query::parse("^src | zzz fin")
+			.indices("src/ui/finder.rs", &mut matcher, &mut buf, &mut indices)
+			.unwrap();
+		assert_eq!(vec![0, 1, 2, 7, 8, 9], indices);
+	}
+
+	#[rstest]
+	#[case::extend("fo", "foo", true)]
+	#[case::backspace("foo", "fo", false)]
+	#[case::new_term("src", "src rs", true)]
+	#[case::drop_term("src rs", "src", false)]
+	#[case::negation_extend("!tes", "!test", false)]
+	#[case::negation_shrink("!test", "!tes", true)]
+	#[case::or_broader("a", "a | b", false)]
+	#[case::or_narrower("a | b", "a", true)]
+	#[case::exact("'ab", "'abc", true)]
+	#[case::prefix("^sr", "^src", true)]
+	#[case::prefix_to_exact("^src", "'src", false)]
+	#[case::exact_to_fuzzy("'src", "src", false)]
+	#[case::exact_to_longer_fuzzy("'src", "srcs", false)]
+	#[case::fuzzy_to_exact("src", "'src", true)]
+	#[case::smart_case("foo", "Foo", true)]
+	#[case::strict_case("Foo", "foo", false)]
+	fn narrows(#[case] old: &str, #[case] new: &str, #[case] expected: bool) {
+		assert_eq!(expected, Query::parse(new).narrows(&Query::parse(old)), "{} -> {}", old, new);
+	}
+
+	#[rstest]
+	#[case::path(Scope::Path, "src/ui/finder.rs", (0, "src/ui/finder.rs"))]
+	#[case::name(Scope::Name, "src/ui/finder.rs", (7, "finder.rs"))]
+	#[case::dir(Scope::Dir, "src/ui/finder.rs", (0, "src/ui/"))]
+	#[case::name_of_dir(Scope::Name, "src/ui/", (4, "ui/"))]
+	#[case::top_level(Scope::Dir, "Cargo.toml", (0, ""))]
+	#[case::graphemes(Scope::Name, "e\u{301}/a", (2, "a"))]
+	fn split(#[case] scope: Scope, #[case] line: &str, #[case] expected: (u32, &str)) {
+		assert_eq!(expected, scope.split(line));
+	}
+
+	#[test]
+	fn empty_query() {
+		assert!(Query::parse("  | ").is_empty());
+	}
+}
@@ -0,0 +1,35 @@
+use std::{
+	io::{self, Write},
+	path::Path
+};
+
+use crate::fileinfo::FilePath;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PathFormat {
+	/// Relative to the directory ffp was started in, even after browsing elsewhere
+	#[default]
+	Relative,
+	Absolute
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OutputFormat {
+	pub path: PathFormat,
+	/// Terminate every path with NUL instead of newline, for `xargs -0`
+	pub print0: bool
+}
+
+pub fn print_paths(paths: &[FilePath], format: OutputFormat, base: &Path) -> io::Result<()> {
+	let mut out = io::stdout().lock();
+	let terminator: &[u8] = if format.print0 { b"\0" } else { b"\n" };
+	for path in paths {
+		let line = match format.path {
+			PathFormat::Relative => path.relative(base),
+			PathFormat::Absolute => path.absolute()
+		};
+		out.write_all(line.as_bytes())?;
+		out.write_all(terminator)?;
+	}
+	out.flush()
+}
@@ -1,10 +1,16 @@
-use std::io::{stdout, Stdout};
+use std::{
+	io::{stderr, Stderr},
+	path::Path
+};
 
 use chin_tools::wrapper::anyhow::AResult;
 use crossterm::{
-	event::Event,
+	event::{DisableMouseCapture, EnableMouseCapture, Event, EventStream},
 	execute,
-	terminal::{BeginSynchronizedUpdate, EndSynchronizedUpdate}
+	terminal::{
+		disable_raw_mode, enable_raw_mode, BeginSynchronizedUpdate, EndSynchronizedUpdate,
+		EnterAlternateScreen, LeaveAlternateScreen
+	}
 };
 use futures_util::{FutureExt, StreamExt};
 use ratatui::{
@@ -12,18 +18,22 @@ use ratatui::{
 	layout::{Constraint, Layout, Rect},
 	Frame, Terminal
 };
+use ratatui_image::picker::Picker;
 
 use crate::{
-	dirwalker::{self, DirFilter},
-	fileinfo::FileInfo,
+	command::{self, CommandTemplate, ExecMode, Placeholders},
+	dirwalker::{self, DirFilter, FindType, Source},
+	fileinfo::{FileInfo, FilePath},
+	keymap::{Action, BuiltinAction},
 	ui::{
 		finder::{Finder, FinderIn},
-		input::Input,
+		input::{Input, InputIn},
 		preview::FileViewer,
-		status::Status,
-		theme::{SharedTheme, Theme},
-		Component, RedrawP
-	}
+		status::{Status, StatusIn},
+		theme::SharedTheme,
+		Component
+	},
+	AppState
 };
 
 #[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Hash)]
@@ -44,26 +54,50 @@ bitflags::bitflags! {
 	}
 }
 
+/// How the user left the tui, decides what `main` prints and the exit code
+pub enum TuiExit {
+	Accept(Vec<FilePath>),
+	NoMatch,
+	Abort
+}
+
+impl TuiExit {
+	pub fn code(&self) -> i32 {
+		match self {
+			TuiExit::Accept(_) => 0,
+			TuiExit::NoMatch => 1,
+			TuiExit::Abort => 130
+		}
+	}
+}
+
 pub struct Tui {
 	theme: SharedTheme,
-	initial_wd: String,
+	state: AppState,
+	picker: Picker,
 	cur_file: Option<FileInfo>
 }
 
 impl Tui {
-	pub fn new(initial_wd: &str) -> Self {
-		let theme = SharedTheme::new(Theme::default());
+	pub fn new(state: AppState, picker: Picker) -> Self {
+		let theme = SharedTheme::new(state.theme.clone());
 		Tui {
 			theme,
-			initial_wd: initial_wd.to_string(),
+			state,
+			picker,
 			cur_file: None
 		}
 	}
 
-	pub fn layout(frame: &Frame<'_>) -> Areas {
+	pub fn layout(frame: &Frame<'_>, preview: bool) -> Areas {
+		let stage_constraint = if preview {
+			Constraint::Fill(1)
+		} else {
+			Constraint::Length(0)
+		};
 		let lr = Layout::default()
 			.direction(ratatui::layout::Direction::Horizontal)
-			.constraints([Constraint::Fill(1), Constraint::Fill(1)])
+			.constraints([Constraint::Fill(1), stage_constraint])
 			.horizontal_margin(1)
 			.vertical_margin(1)
 			.split(frame.size());
@@ -88,7 +122,62 @@ impl Tui {
 		}
 	}
 
-	pub async fn run(&mut self, term: &mut Terminal<CrosstermBackend<Stdout>>) -> AResult<()> {
+	/// Hands the terminal back to the shell, e.g. for a foreground command
+	fn suspend(term: &mut Terminal<CrosstermBackend<Stderr>>) -> AResult<()> {
+		execute!(
+			term.backend_mut(),
+			LeaveAlternateScreen,
+			DisableMouseCapture,
+			crossterm::cursor::Show
+		)?;
+		disable_raw_mode()?;
+		Ok(())
+	}
+
+	fn resume(term: &mut Terminal<CrosstermBackend<Stderr>>) -> AResult<()> {
+		enable_raw_mode()?;
+		execute!(
+			term.backend_mut(),
+			EnterAlternateScreen,
+			EnableMouseCapture,
+			crossterm::cursor::Hide
+		)?;
+		term.clear()?;
+		Ok(())
+	}
+
+	/// Foreground commands are awaited here, background ones report failures on `status_tx`
+	async fn execute(
+		term: &mut Terminal<CrosstermBackend<Stderr>>,
+		template: &CommandTemplate,
+		ph: &Placeholders<'_>,
+		status_tx: &flume::Sender<StatusIn>
+	) -> AResult<Option<String>> {
+		let command = template.command(ph);
+		match template.mode {
+			ExecMode::Foreground => {
+				Tui::suspend(term)?;
+				let res = command::run(command).await;
+				Tui::resume(term)?;
+				Ok(res.err().map(|err| err.to_string()))
+			}
+			// key actions never capture, the output would go nowhere anyway
+			ExecMode::Background | ExecMode::Capture => {
+				let status_tx = status_tx.clone();
+				tokio::spawn(async move {
+					if let Err(err) = command::run(command).await {
+						status_tx
+							.send(StatusIn::Message(Some(err.to_string())))
+							.map_err(|err| tracing::error!("unable to send status msg: {}", err))
+							.ok();
+					}
+				});
+				Ok(None)
+			}
+		}
+	}
+
+	pub async fn run(&mut self, term: &mut Terminal<CrosstermBackend<Stderr>>) -> AResult<TuiExit> {
 		let (input_out_tx, input_out_rx) = flume::unbounded();
 		let mut input_out_rx = input_out_rx.stream();
 
@@ -101,24 +190,56 @@ impl Tui {
 		let (stage_out_tx, stage_out_rx) = flume::unbounded();
 		let mut stage_out_rx = stage_out_rx.stream();
 
-		let mut ev_stream = crossterm::event::EventStream::new();
+		let (status_in_tx, status_in_rx) = flume::unbounded();
+		let mut status_in_rx = status_in_rx.stream();
 
-		let cwd = self.initial_wd.as_str();
-		dirwalker::rebuild_dirlist_start(finder_in_tx.clone(), cwd, DirFilter::builder().build());
+		let mut ev_stream = EventStream::new();
 
-		let mut input = Input::new(input_out_tx);
-		let mut finder = Finder::new(self.theme.clone(), finder_out_tx);
-		let mut status = Status::new(cwd);
-		let mut viewer = FileViewer::new(stage_out_tx);
+		let mut cwd = self.state.cwd.clone();
+		finder_in_tx
+			.send(FinderIn::Query(self.state.query.clone()))
+			.map_err(|err| tracing::error!("unable to send Query msg: {}", err))
+			.ok();
+		let mut filter = DirFilter::builder()
+			.with_find_type(self.state.show_mode)
+			.with_dotfile(self.state.show_hidden)
+			.with_ignore(self.state.ignore)
+			.build();
+		match self.state.source {
+			Source::Walk =>
+				dirwalker::rebuild_dirlist_start(finder_in_tx.clone(), &cwd, filter.clone()),
+			Source::Stdin => dirwalker::read_stdin_start(finder_in_tx.clone(), &cwd, self.state.ansi)
+		}
+
+		let mut input = Input::new(input_out_tx, &self.state.query);
+		let mut finder = Finder::new(self.theme.clone(), finder_out_tx, self.state.sort);
+		let mut status = Status::new(&cwd);
+		status.handle_msg(StatusIn::ShowType(self.state.show_mode));
+		status.handle_msg(StatusIn::ShowHide(self.state.show_hidden));
+		status.handle_msg(StatusIn::Ignore(
+			self.state.source == Source::Walk && filter.ignore()
+		));
+		status.handle_msg(StatusIn::Sort(finder.is_ranked()));
+		status.handle_msg(StatusIn::Scope(finder.scope()));
+		let mut viewer = FileViewer::new(stage_out_tx, self.state.preview.clone(), self.picker);
+
+		// --select-1/--exit-0 only look at the first result that covers all candidates
+		let mut auto_accept_pending = self.state.select_1 || self.state.exit_0;
+
+		macro_rules! restart_walk {
+			() => {
+				dirwalker::rebuild_dirlist_start(finder_in_tx.clone(), &cwd, filter.clone())
+			};
+		}
 
 		let mut changed_coms = ComponentEnum::all();
 
 		Ok(loop {
 			let frame = term.get_frame();
-			let areas = Tui::layout(&frame);
+			let areas = Tui::layout(&frame, self.state.preview.enabled);
 
 			term.draw(|f| {
-				execute!(stdout(), BeginSynchronizedUpdate).unwrap();
+				execute!(stderr(), BeginSynchronizedUpdate).unwrap();
 				input
 					.draw(f, &areas.input, changed_coms.contains(ComponentEnum::INPUT))
 					.unwrap();
@@ -138,38 +259,137 @@ impl Tui {
 						changed_coms.contains(ComponentEnum::STATUS)
 					)
 					.unwrap();
-				viewer.view(f, &areas.stage);
+				if self.state.preview.enabled {
+					viewer.view(f, &areas.stage);
+				}
 			})?;
 
-			execute!(stdout(), EndSynchronizedUpdate)?;
+			execute!(stderr(), EndSynchronizedUpdate)?;
 
 			changed_coms = tokio::select! {
 				Some(ev) = ev_stream.next().fuse() => {
 					tracing::trace!("msg: ev stream");
-					let redraw = ComponentEnum::empty();
-					if let Ok(ev) = ev {
-						if let Event::Key(key) = ev.clone() {
-							if key.code == crossterm::event::KeyCode::Esc {
-								break
+					let Ok(ev) = ev else {
+						continue;
+					};
+					let action = match &ev {
+						Event::Key(key) => self.state.keymap.get(*key).cloned(),
+						_ => None
+					};
+					let walking = self.state.source == Source::Walk;
+					let ls = filter.find_type() == FindType::LS;
+
+					match action {
+						Some(Action::Execute(template)) => {
+							let selected = finder.accepted();
+							let ph = Placeholders {
+								input: input.text(),
+								selected: &selected,
+								cwd: &cwd
+							};
+							let res = if template.mode == ExecMode::Foreground {
+								// the event reader thread would steal keys from the command
+								drop(ev_stream);
+								let res = Tui::execute(term, &template, &ph, &status_in_tx).await;
+								ev_stream = EventStream::new();
+								res
+							} else {
+								Tui::execute(term, &template, &ph, &status_in_tx).await
+							};
+							let msg = match res {
+								Ok(msg) => msg,
+								Err(err) => Some(err.to_string())
+							};
+							status.handle_msg(StatusIn::Message(msg));
+							ComponentEnum::all()
+						}
+						Some(Action::Builtin(BuiltinAction::Accept)) => {
+							let accepted = finder.accepted();
+							if accepted.is_empty() {
+								break TuiExit::NoMatch
 							}
+							break TuiExit::Accept(accepted)
+						}
+						Some(Action::Builtin(BuiltinAction::Abort)) => break TuiExit::Abort,
+						Some(Action::Builtin(BuiltinAction::ToggleIgnore)) if walking => {
+							let ignore = !filter.ignore();
+							filter = filter.into_builder().with_ignore(ignore).build();
+							restart_walk!();
+							status.handle_msg(StatusIn::Ignore(ignore));
+							ComponentEnum::STATUS
 						}
+						Some(Action::Builtin(BuiltinAction::ToggleHidden)) if walking => {
+							let dotfile = !filter.dotfile();
+							filter = filter.into_builder().with_dotfile(dotfile).build();
+							restart_walk!();
+							status.handle_msg(StatusIn::ShowHide(dotfile));
+							ComponentEnum::STATUS
+						}
+						Some(Action::Builtin(BuiltinAction::ToggleMode)) if walking => {
+							let find_type = if ls { FindType::FIND } else { FindType::LS };
+							filter = filter.into_builder().with_find_type(find_type).build();
+							restart_walk!();
+							status.handle_msg(StatusIn::ShowType(find_type));
+							ComponentEnum::STATUS
+						}
+						Some(Action::Builtin(action @ (BuiltinAction::EnterDir | BuiltinAction::ParentDir)))
+							if walking && ls =>
+						{
+							let new_cwd = if action == BuiltinAction::EnterDir {
+								finder
+									.selected()
+									.filter(|sel| sel.path().is_dir())
+									.map(|dir| dir.path().to_string_lossy().to_string())
+							} else {
+								Path::new(&cwd)
+									.parent()
+									.map(|parent| parent.to_string_lossy().to_string())
+							};
 
-						let res = input.handle_event(ev.clone());
-						if res.1.yes() {
-							if RedrawP::Yes == res.0 {
-								changed_coms = ComponentEnum::INPUT
+							match new_cwd {
+								Some(new_cwd) => {
+									cwd = new_cwd;
+									restart_walk!();
+									status.handle_msg(StatusIn::CWD(cwd.clone()));
+									input.handle_msg(InputIn::Clear);
+									ComponentEnum::STATUS | ComponentEnum::INPUT
+								}
+								None => ComponentEnum::empty()
 							}
 						}
-
-						if !res.1.yes() {
-							let res = finder.handle_event(ev.clone());
-							if res.0.yes() {
-								changed_coms |= ComponentEnum::FINDER
+						Some(Action::Builtin(action)) => {
+							let mut redraw = ComponentEnum::empty();
+							if input.handle_action(action).yes() {
+								redraw |= ComponentEnum::INPUT;
+							}
+							if finder.handle_action(action).yes() {
+								status.set_marked_count(finder.marked_count());
+								status.handle_msg(StatusIn::Sort(finder.is_ranked()));
+								status.handle_msg(StatusIn::Scope(finder.scope()));
+								redraw |= ComponentEnum::FINDER | ComponentEnum::STATUS;
+							}
+							if viewer.handle_action(action).yes() {
+								redraw |= ComponentEnum::STAGE;
+							}
+							redraw
+						}
+						None => match ev {
+							Event::Mouse(mouse) => {
+								if viewer.handle_mouse(mouse, &areas.stage).yes() {
+									ComponentEnum::STAGE
+								} else {
+									ComponentEnum::empty()
+								}
+							}
+							ev => {
+								if input.handle_event(ev).0.yes() {
+									ComponentEnum::INPUT
+								} else {
+									ComponentEnum::empty()
+								}
 							}
 						}
 					}
-
-					redraw
 				},
 				Some(ev) = input_out_rx.next() => {
 					match ev {
@@ -179,26 +399,40 @@ impl Tui {
 								tracing::error!("unable to send Query msg: {}", err)
 							})
 							.ok();
-							ComponentEnum::INPUT
+							status.handle_msg(StatusIn::Message(None));
+							ComponentEnum::INPUT | ComponentEnum::STATUS
 						},
 					}
 				},
 				Some(ev) = finder_in_rx.next() => {
 					tracing::trace!("msg: finder in rx");
 					finder.handle_msg(ev);
-					ComponentEnum::empty()
+					status.set_marked_count(finder.marked_count());
+					ComponentEnum::STATUS
 				},
 				Some(ev) = finder_out_rx.next() => {
 					tracing::trace!("msg: finder out rx");
 					match ev {
-						crate::ui::finder::FinderOut::FilteAResult(query, fr) => {
-							status.set_filter_count(fr.len());
-							finder.update_filter(query, fr);
+						crate::ui::finder::FinderOut::FilteAResult(ticket, query, scope, fr, highlights) => {
+							if finder.update_filter(ticket, query, scope, fr, highlights) {
+								status.set_filter_count(finder.filtered_len());
+
+								if auto_accept_pending && finder.is_loaded() {
+									auto_accept_pending = false;
+									match finder.filtered_len() {
+										0 if self.state.exit_0 => break TuiExit::NoMatch,
+										1 if self.state.select_1 => break TuiExit::Accept(finder.accepted()),
+										_ => {}
+									}
+								}
+							}
 
 							ComponentEnum::FINDER | ComponentEnum::STATUS
 						},
 						crate::ui::finder::FinderOut::Selected(selected) => {
-							viewer.handle_file(&selected);
+							if self.state.preview.enabled {
+								viewer.handle_file(&selected, input.text(), &cwd);
+							}
 							self.cur_file.replace(selected.clone().into());
 
 							ComponentEnum::STAGE
@@ -213,6 +447,11 @@ impl Tui {
 					viewer.set_view(ev);
 
 					ComponentEnum::STAGE
+				},
+				Some(ev) = status_in_rx.next() => {
+					status.handle_msg(ev);
+
+					ComponentEnum::STATUS
 				}
 			};
 		})
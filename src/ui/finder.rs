@@ -1,34 +1,40 @@
 use std::{
 	borrow::Cow,
 	cell::RefCell,
+	cmp::Ordering as CmpOrdering,
+	collections::{HashMap, HashSet},
 	rc::Rc,
 	sync::{
 		atomic::{AtomicU64, Ordering},
-		Arc, RwLock
+		Arc, Mutex, RwLock
 	},
 	thread
 };
 
-use crossterm::event::Event;
 use flume::Sender;
-use fuzzy_matcher::FuzzyMatcher;
+use nucleo_matcher::Matcher;
 use ratatui::{
 	layout::Rect,
 	text::{Line, Span},
 	widgets::{Block, Borders},
 	Frame
 };
-use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use rayon::{
+	iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator},
+	slice::ParallelSliceMut
+};
 use tracing::{error, info};
 use unicode_segmentation::UnicodeSegmentation;
 
-use super::{theme::SharedTheme, Component, ConsumeP, RedrawP};
+use super::{theme::SharedTheme, Component, RedrawP};
 use crate::{
 	componment::{
 		scrollbar::{self, Orientation},
 		scrolllist::ScrollableList
 	},
-	fileinfo::FilePath
+	fileinfo::FilePath,
+	keymap::BuiltinAction,
+	matcher::{self, Query, Scope}
 };
 
 #[derive(Debug)]
@@ -36,6 +42,8 @@ pub enum FinderIn {
 	Clear,
 	Refresh,
 	ContentsExtend(Vec<FilePath>),
+	/// The source has sent everything it has
+	Finished,
 	Query(String)
 }
 
@@ -46,9 +54,19 @@ pub enum FinderMove {
 	Nil
 }
 
+/// Matched grapheme positions by index into the contents
+pub type Highlights = HashMap<usize, Arc<[u32]>>;
+
+/// The filter worker computes the highlights of this many top results along with them
+const PREFETCH_HIGHLIGHTS: usize = 128;
+/// More cached highlights than this and the cache starts over
+const MAX_HIGHLIGHTS: usize = 10_000;
+
 #[derive(Debug)]
 pub enum FinderOut {
-	FilteAResult(String, FileteAResultEnum),
+	/// The ticket of the filter task, the query, its result and the highlights of the first
+	/// results
+	FilteAResult(u64, String, Scope, FileteAResultEnum, Highlights),
 	Selected(FilePath),
 	TotalCount(usize)
 }
@@ -76,27 +94,150 @@ impl FileteAResultEnum {
 	}
 }
 
+/// The highlights of the shown result, rows the worker didn't prefetch are matched once
+/// when they are first drawn
+struct Highlighter {
+	query: Query,
+	scope: Scope,
+	matcher: Matcher,
+	buf: Vec<char>,
+	cache: Highlights
+}
+
+impl Highlighter {
+	fn new() -> Self {
+		Self {
+			query: Query::default(),
+			scope: Scope::default(),
+			matcher: matcher::new_matcher(),
+			buf: vec![],
+			cache: HashMap::new()
+		}
+	}
+
+	fn reset(&mut self, query: &str, scope: Scope, prefetched: Highlights) {
+		self.query = Query::parse(query);
+		self.scope = scope;
+		self.cache = prefetched;
+	}
+
+	fn extend(&mut self, prefetched: Highlights) {
+		self.cache.extend(prefetched);
+	}
+
+	fn indices(&mut self, index: usize, line: &str) -> Arc<[u32]> {
+		if let Some(indices) = self.cache.get(&index) {
+			return indices.clone();
+		}
+		if self.cache.len() >= MAX_HIGHLIGHTS {
+			self.cache.clear();
+		}
+
+		// indices within the matched part, shifted to where it starts in the line
+		let (offset, part) = self.scope.split(line);
+		let mut indices = vec![];
+		self.query
+			.indices(part, &mut self.matcher, &mut self.buf, &mut indices);
+		let indices: Arc<[u32]> = indices.into_iter().map(|i| i + offset).collect();
+		self.cache.insert(index, indices.clone());
+		indices
+	}
+}
+
 pub struct Finder {
 	out_tx: Sender<FinderOut>,
 	theme: SharedTheme,
 	selection: Option<usize>,
+	/// Indexes into `contents` toggled with Tab/Shift-Tab
+	marked: HashSet<usize>,
 	show_start: usize,
 	last_move: FinderMove,
 
 	cached_selection: Rc<RefCell<Option<FilePath>>>,
 
 	contents: Arc<RwLock<Vec<FilePath>>>,
+	loaded: bool,
 	query: String,
+	/// Best matches first, otherwise in the order the source sent them
+	ranked: bool,
+	scope: Scope,
 	filtered: FileteAResultEnum,
+	/// The query and scope `filtered` is the result of
+	filtered_query: String,
+	filtered_scope: Scope,
+	highlighter: RefCell<Highlighter>,
+	filter_ticket: u64,
 	filter_worker: FilterWorker
 }
 
 struct FilterWorkerMsg {
 	query: String,
+	scope: Scope,
+	ranked: bool,
 	contents: Arc<RwLock<Vec<FilePath>>>,
 	out_tx: Sender<FinderOut>
 }
 
+#[derive(Debug, Clone, Copy)]
+struct Match {
+	index: usize,
+	score: u32,
+	/// The query matches within the file name alone
+	basename: bool,
+	len: usize
+}
+
+impl Match {
+	/// Higher scores first, then file name matches, shorter paths and at last the walk
+	/// order, so equal matches keep their order
+	fn rank(a: &Match, b: &Match) -> CmpOrdering {
+		b.score
+			.cmp(&a.score)
+			.then(b.basename.cmp(&a.basename))
+			.then(a.len.cmp(&b.len))
+			.then(a.index.cmp(&b.index))
+	}
+
+	fn sort(matches: &mut [Match], ranked: bool) {
+		if ranked {
+			matches.par_sort_unstable_by(Match::rank);
+		} else {
+			matches.par_sort_unstable_by_key(|m| m.index);
+		}
+	}
+
+	/// Merges two lists that are each sorted by [`Match::rank`]
+	fn merge_ranked(a: Vec<Match>, b: Vec<Match>) -> Vec<Match> {
+		let mut merged = Vec::with_capacity(a.len() + b.len());
+		let mut a = a.into_iter().peekable();
+		let mut b = b.into_iter().peekable();
+		loop {
+			let take_b = match (a.peek(), b.peek()) {
+				(Some(x), Some(y)) => Match::rank(x, y) == CmpOrdering::Greater,
+				(Some(_), None) => false,
+				(None, _) => true
+			};
+			match if take_b { b.next() } else { a.next() } {
+				Some(m) => merged.push(m),
+				None => break merged
+			}
+		}
+	}
+}
+
+/// Filter results kept around for refining, one per recent query
+const MAX_CACHED_FILTERS: usize = 16;
+
+/// The matches of `query` among the first `scanned` contents
+struct FilterCache {
+	query: String,
+	parsed: Query,
+	scope: Scope,
+	ranked: bool,
+	scanned: usize,
+	matches: Vec<Match>
+}
+
 pub trait FinderItem: 'static {
 	fn line(&self);
 }
@@ -104,22 +245,31 @@ pub trait FinderItem: 'static {
 #[derive(Default)]
 struct FilterWorker {
 	filter_task_handler: Arc<AtomicU64>,
-	filter_result: Option<Arc<(String, Vec<usize>)>>
+	/// Recent results, newest last. More contents for a cached query only score the new
+	/// ones, a narrower query only rescores the matches of a broader one
+	cache: Arc<Mutex<Vec<Arc<FilterCache>>>>
 }
 
 impl FilterWorker {
-	fn filter_start(&mut self, msg: FilterWorkerMsg) {
+	/// The contents were replaced, nothing cached or running refers to them anymore
+	fn reset(&mut self) {
+		let mut cache = self.cache.lock().unwrap();
+		self.filter_task_handler.fetch_add(1, Ordering::Relaxed);
+		cache.clear();
+	}
+
+	fn filter_start(&mut self, msg: FilterWorkerMsg) -> u64 {
 		let handler = self.filter_task_handler.clone();
-		handler.fetch_add(1, Ordering::Relaxed);
+		let ticket = handler.fetch_add(1, Ordering::Relaxed) + 1;
 
 		let query = msg.query.clone();
+		let scope = msg.scope;
+		let ranked = msg.ranked;
 		let content = msg.contents.clone();
 		let sender = msg.out_tx.clone();
-		let filtered = self.filter_result.clone();
+		let cache = self.cache.clone();
 
 		thread::spawn(move || {
-			let ticket = handler.load(Ordering::Relaxed);
-			let matcher = fuzzy_matcher::skim::SkimMatcherV2::default();
 			macro_rules! maybe_stop {
 				() => {
 					if handler.load(Ordering::Relaxed) != ticket {
@@ -136,71 +286,169 @@ impl FilterWorker {
 				};
 			}
 
-			if query.is_empty() {
+			let parsed = Query::parse(&query);
+			if parsed.is_empty() {
 				maybe_stop!();
 
 				sender
 					.send(FinderOut::FilteAResult(
+						ticket,
 						query.clone(),
-						FileteAResultEnum::All(content.read().unwrap().len())
+						scope,
+						FileteAResultEnum::All(content.read().unwrap().len()),
+						HashMap::new()
 					))
 					.map_err(|err| error!("unable to send content extend msg: {}", err))
 					.ok();
 			} else {
 				let content = content.read().unwrap();
-
-				let fr = if filtered.is_some()
-					&& query.contains(filtered.as_ref().unwrap().0.as_str())
-				{
-					filtered
-						.as_ref()
-						.unwrap()
-						.1
-						.par_iter()
-						.filter_map(|s| {
-							maybe_stop2!();
-							if let Some(line) = content.get(*s) {
-								matcher
-									.fuzzy_indices(line.line(), query.as_ref())
-									.map(|(score, indices)| (score, *s, indices))
-							} else {
-								None
-							}
+				// the same query when typed again, otherwise the smallest result of a
+				// query this one narrows
+				let base = {
+					let cache = cache.lock().unwrap();
+					let usable = cache
+						.iter()
+						.filter(|cached| cached.scope == scope && cached.scanned <= content.len())
+						.collect::<Vec<_>>();
+					usable
+						.iter()
+						.find(|cached| cached.query == query)
+						.or_else(|| {
+							usable
+								.iter()
+								.filter(|cached| parsed.narrows(&cached.parsed))
+								.min_by_key(|cached| cached.matches.len())
 						})
-						.map(|e| e.1)
-						.collect::<Vec<usize>>()
-				} else {
-					content
-						.par_iter()
-						.enumerate()
-						.filter_map(|(i, s)| {
-							maybe_stop2!();
-							matcher
-								.fuzzy_indices(s.line(), query.as_ref())
-								.map(|(score, indices)| (score, i, indices))
-						})
-						.map(|e| e.1)
-						.collect::<Vec<usize>>()
+						.map(|cached| Arc::clone(cached))
+				};
+
+				// a matcher and its char buffer per rayon thread
+				let init = || (matcher::new_matcher(), Vec::new());
+				let score_at = |index: usize, matcher: &mut Matcher, buf: &mut Vec<char>| {
+					let line = content[index].line();
+					let score = parsed.score(scope.split(line).1, matcher, buf)?;
+					let basename = match scope {
+						// a line without a directory was just scored as a name
+						Scope::Path => match Scope::Name.split(line) {
+							(0, _) => true,
+							(_, name) => parsed.score(name, matcher, buf).is_some()
+						},
+						Scope::Name => true,
+						Scope::Dir => false
+					};
+					Some(Match {
+						index,
+						score,
+						basename,
+						len: line.len()
+					})
+				};
+
+				let (start, mut matches) = match base {
+					Some(base) if base.query == query => {
+						let mut matches = base.matches.clone();
+						if base.ranked != ranked {
+							Match::sort(&mut matches, ranked);
+						}
+						(base.scanned, matches)
+					}
+					Some(base) => {
+						let mut matches = base
+							.matches
+							.par_iter()
+							.map_init(init, |(matcher, buf), m| {
+								maybe_stop2!();
+								score_at(m.index, matcher, buf)
+							})
+							.flatten()
+							.collect::<Vec<Match>>();
+						maybe_stop!();
+						Match::sort(&mut matches, ranked);
+						(base.scanned, matches)
+					}
+					None => (0, vec![])
 				};
 
+				// contents that arrived after the base result was cached are all scanned
+				let mut found = (start..content.len())
+					.into_par_iter()
+					.map_init(init, |(matcher, buf), index| {
+						maybe_stop2!();
+						score_at(index, matcher, buf)
+					})
+					.flatten()
+					.collect::<Vec<Match>>();
+
+				maybe_stop!();
+
+				if ranked {
+					Match::sort(&mut found, true);
+					matches = Match::merge_ranked(matches, found);
+				} else {
+					// new contents come after the old ones, the walk order holds
+					matches.extend(found);
+				}
+
 				maybe_stop!();
 
+				let fr = matches.iter().map(|m| m.index).collect::<Vec<usize>>();
+
+				// the first page is drawn right away, spare the ui thread from matching it
+				let mut highlighter = Highlighter::new();
+				highlighter.reset(&query, scope, HashMap::new());
+				for idx in fr.iter().take(PREFETCH_HIGHLIGHTS) {
+					highlighter.indices(*idx, content[*idx].line());
+				}
+				{
+					// checked under the lock, a reset in between must not see stale matches
+					let mut cache = cache.lock().unwrap();
+					maybe_stop!();
+					cache.retain(|cached| cached.query != query);
+					if cache.len() >= MAX_CACHED_FILTERS {
+						cache.remove(0);
+					}
+					cache.push(Arc::new(FilterCache {
+						query: query.clone(),
+						parsed,
+						scope,
+						ranked,
+						scanned: content.len(),
+						matches
+					}));
+				}
+
 				sender
-					.send(FinderOut::FilteAResult(query.clone(), fr.into()))
+					.send(FinderOut::FilteAResult(
+						ticket,
+						query.clone(),
+						scope,
+						fr.into(),
+						highlighter.cache
+					))
 					.map_err(|err| error!("unable to send content extend msg: {}", err))
 					.ok();
 			}
 		});
+
+		ticket
 	}
 }
 impl Finder {
-	pub fn new(theme: SharedTheme, out_tx: Sender<FinderOut>) -> Finder {
+	pub fn new(theme: SharedTheme, out_tx: Sender<FinderOut>, ranked: bool) -> Finder {
 		Self {
 			out_tx,
 			query: "".to_string(),
+			ranked,
+			scope: Scope::default(),
 			contents: Arc::new(RwLock::new(vec![])),
+			loaded: false,
 			selection: Some(0),
+			marked: HashSet::new(),
 			filtered: FileteAResultEnum::All(0),
+			filtered_query: "".to_string(),
+			filtered_scope: Scope::default(),
+			highlighter: RefCell::new(Highlighter::new()),
+			filter_ticket: 0,
 			theme,
 			show_start: 0,
 			filter_worker: Default::default(),
@@ -209,13 +457,38 @@ impl Finder {
 		}
 	}
 
-	pub fn update_filter(&mut self, query: String, filter: FileteAResultEnum) {
-		if query == self.query {
-			self.filtered = filter;
+	/// Returns false when the result is from an outdated filter task and was dropped
+	pub fn update_filter(
+		&mut self,
+		ticket: u64,
+		query: String,
+		scope: Scope,
+		filter: FileteAResultEnum,
+		highlights: Highlights
+	) -> bool {
+		if ticket != self.filter_ticket {
+			return false;
+		}
+
+		let highlighter = self.highlighter.get_mut();
+		if query == self.filtered_query && scope == self.filtered_scope {
+			// more contents for the same query, the cached rows are still right
+			highlighter.extend(highlights);
+		} else {
+			highlighter.reset(&query, scope, highlights);
 		}
+		self.filtered = filter;
+		self.filtered_query = query;
+		self.filtered_scope = scope;
+		true
+	}
+
+	/// Whether the source has finished sending contents
+	pub fn is_loaded(&self) -> bool {
+		self.loaded
 	}
 
-	fn filtered_len(&self) -> usize {
+	pub fn filtered_len(&self) -> usize {
 		match &self.filtered {
 			FileteAResultEnum::All(c) => *c,
 			FileteAResultEnum::Vec(vec) => vec.len(),
@@ -223,9 +496,105 @@ impl Finder {
 		}
 	}
 
+	pub fn selected(&self) -> Option<FilePath> {
+		let idx = self.selected_index()?;
+		self.contents.read().unwrap().get(idx).cloned()
+	}
+
+	/// The marked entries in walk order, or the selection when nothing is marked
+	pub fn accepted(&self) -> Vec<FilePath> {
+		if self.marked.is_empty() {
+			return self.selected().into_iter().collect();
+		}
+
+		let contents = self.contents.read().unwrap();
+		let mut marked: Vec<usize> = self.marked.iter().copied().collect();
+		marked.sort_unstable();
+		marked
+			.into_iter()
+			.filter_map(|idx| contents.get(idx).cloned())
+			.collect()
+	}
+
+	pub fn is_ranked(&self) -> bool {
+		self.ranked
+	}
+
+	pub fn scope(&self) -> Scope {
+		self.scope
+	}
+
+	pub fn marked_count(&self) -> usize {
+		self.marked.len()
+	}
+
+	fn selected_index(&self) -> Option<usize> {
+		let selection = self.selection?;
+		match &self.filtered {
+			FileteAResultEnum::All(count) => (selection < *count).then_some(selection),
+			FileteAResultEnum::Vec(vec) => vec.get(selection).copied(),
+			FileteAResultEnum::None => None
+		}
+	}
+
+	fn filtered_indexes(&self) -> Vec<usize> {
+		match &self.filtered {
+			FileteAResultEnum::All(count) => (0..*count).collect(),
+			FileteAResultEnum::Vec(vec) => vec.as_ref().clone(),
+			FileteAResultEnum::None => vec![]
+		}
+	}
+
+	fn toggle_mark(&mut self, move_type: FinderMove) {
+		if let Some(idx) = self.selected_index() {
+			if !self.marked.remove(&idx) {
+				self.marked.insert(idx);
+			}
+		}
+		self.move_selection(move_type);
+	}
+
+	fn mark_all(&mut self, mark: bool) {
+		let indexes = self.filtered_indexes();
+		if mark {
+			self.marked.extend(indexes);
+		} else {
+			indexes.iter().for_each(|idx| {
+				self.marked.remove(idx);
+			});
+		}
+	}
+
+	pub fn handle_action(&mut self, action: BuiltinAction) -> RedrawP {
+		match action {
+			BuiltinAction::Up => {
+				self.move_selection(FinderMove::Up);
+			}
+			BuiltinAction::Down => {
+				self.move_selection(FinderMove::Down);
+			}
+			BuiltinAction::ToggleDown => self.toggle_mark(FinderMove::Down),
+			BuiltinAction::ToggleUp => self.toggle_mark(FinderMove::Up),
+			BuiltinAction::SelectAll => self.mark_all(true),
+			BuiltinAction::DeselectAll => self.mark_all(false),
+			BuiltinAction::ToggleSort => {
+				self.ranked = !self.ranked;
+				self.filter_start();
+			}
+			BuiltinAction::CycleScope => {
+				self.scope = self.scope.next();
+				self.filter_start();
+			}
+			_ => return RedrawP::No
+		}
+		RedrawP::Yes
+	}
+
 	fn filter_start(&mut self) {
-		self.filter_worker.filter_start(FilterWorkerMsg {
+		self.filter_ticket = self.filter_worker.filter_start(FilterWorkerMsg {
 			query: self.query.clone(),
+			scope: self.scope,
+			ranked: self.ranked,
 			contents: self.contents.clone(),
 			out_tx: self.out_tx.clone()
 		});
@@ -310,7 +679,6 @@ impl Component for Finder {
 
 		let scroll_skip = self.show_start;
 		let selection = self.selection;
-		let matcher = fuzzy_matcher::skim::SkimMatcherV2::default();
 
 		let page: Vec<(usize, usize)> = match &self.filtered {
 			FileteAResultEnum::All(_) => self
@@ -353,28 +721,31 @@ impl Component for Finder {
 					}
 				}
 
-				let line = vec[*idx].line();
+				let path = &vec[*idx];
+				let line = path.line();
 				let full_text = line;
 				let trim_length = line.graphemes(true).count() - full_text.graphemes(true).count();
 
-				let indices = matcher
-					.fuzzy_indices(line, &self.query)
-					.map(|(_, indices)| indices);
+				let indices = self.highlighter.borrow_mut().indices(*idx, line);
+				let gutter = if self.marked.contains(idx) {
+					Span::styled("▍", self.theme.marker())
+				} else {
+					Span::raw(" ")
+				};
 				Line::from(
-					full_text
-						.graphemes(true)
-						.enumerate()
-						.map(|(c_idx, c)| {
-							Span::styled(
-								Cow::from(c.to_string()),
-								self.theme.text(
-									indices
-										.as_ref()
-										.map_or(false, |e| e.contains(&(c_idx + trim_length))),
-									selected
-								)
-							)
-						})
+					std::iter::once(gutter)
+						.chain(full_text.grapheme_indices(true).enumerate().map(
+							|(c_idx, (offset, c))| {
+								let enabled = indices
+									.binary_search(&((c_idx + trim_length) as u32))
+									.is_ok();
+								let style = match path.style_at(offset) {
+									Some(ansi) => self.theme.ansi_text(ansi, enabled, selected),
+									None => self.theme.text(enabled, selected)
+								};
+								Span::styled(Cow::from(c.to_string()), style)
+							}
+						))
 						.collect::<Vec<_>>()
 				)
 			})
@@ -383,33 +754,14 @@ impl Component for Finder {
 		ScrollableList::new(items.into_iter()).block(Block::default().borders(Borders::RIGHT))
 	}
 
-	fn handle_event(&mut self, event: Event) -> (RedrawP, ConsumeP) {
-		match event {
-			Event::Key(key) => {
-				/* 				if key.modifiers != KeyModifiers::NONE || key.modifiers != KeyModifiers::SHIFT {
-					return false;
-				} */
-
-				match key.code {
-					crossterm::event::KeyCode::Up => {
-						self.move_selection(FinderMove::Up);
-						(RedrawP::Yes, ConsumeP::Yes)
-					}
-					crossterm::event::KeyCode::Down => {
-						self.move_selection(FinderMove::Down);
-						(RedrawP::Yes, ConsumeP::Yes)
-					}
-					_ => (RedrawP::No, ConsumeP::No)
-				}
-			}
-			_ => (RedrawP::No, ConsumeP::No)
-		}
-	}
-
 	fn handle_msg(&mut self, msg: Self::MsgIn) {
 		match msg {
 			FinderIn::Clear => {
 				self.contents.write().unwrap().clear();
+				self.filter_worker.reset();
+				self.highlighter.get_mut().reset(&self.filtered_query, self.filtered_scope, HashMap::new());
+				self.marked.clear();
+				self.loaded = false;
 				self.filtered = FileteAResultEnum::None;
 			}
 			FinderIn::Refresh => {}
@@ -421,7 +773,40 @@ impl Component for Finder {
 					.send(FinderOut::TotalCount(self.contents.read().unwrap().len()))
 					.unwrap();
 			}
+			FinderIn::Finished => {
+				self.loaded = true;
+				// refilter so there is a result that covers everything
+				let query = self.query.to_string();
+				self.update_query(query.as_str());
+			}
 			FinderIn::Query(query) => self.update_query(query.as_str())
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn m(index: usize, score: u32, basename: bool, len: usize) -> Match {
+		Match {
+			index,
+			score,
+			basename,
+			len
+		}
+	}
+
+	#[test]
+	fn rank_then_merge() {
+		let mut old = vec![m(0, 10, false, 5), m(1, 30, false, 9), m(2, 10, false, 5)];
+		let mut new = vec![m(3, 10, true, 9), m(4, 30, false, 4), m(5, 20, false, 1)];
+		Match::sort(&mut old, true);
+		Match::sort(&mut new, true);
+
+		let merged = Match::merge_ranked(old, new);
+		let order = merged.iter().map(|m| m.index).collect::<Vec<_>>();
+		// score, then basename, then length, then walk order
+		assert_eq!(vec![4, 1, 5, 3, 0, 2], order);
+	}
+}
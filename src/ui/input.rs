@@ -1,4 +1,4 @@
-use crossterm::event::Event;
+use crossterm::event::{Event, KeyModifiers};
 use flume::Sender;
 use ratatui::{
 	layout::Rect,
@@ -6,6 +6,7 @@ use ratatui::{
 };
 
 use super::{Component, ConsumeP, RedrawP};
+use crate::keymap::BuiltinAction;
 
 #[derive(Debug)]
 pub enum InputIn {
@@ -36,16 +37,32 @@ pub struct Input {
 }
 
 impl Input {
-	pub fn new(out_tx: Sender<InputOut>) -> Input {
+	pub fn new(out_tx: Sender<InputOut>, query: &str) -> Input {
 		Input {
 			out_tx,
-			input: "".to_string(),
-			cursor_position: 0,
+			input: query.to_string(),
+			cursor_position: query.len(),
 			show_start: 0,
 			input_move: InputMove::Nil
 		}
 	}
 
+	pub fn handle_action(&mut self, action: BuiltinAction) -> RedrawP {
+		match action {
+			BuiltinAction::BackwardDeleteChar => self.delete_char(),
+			BuiltinAction::BackwardChar => self.move_cursor_left(),
+			BuiltinAction::ForwardChar => self.move_cursor_right(),
+			BuiltinAction::BeginningOfLine => self.input_move = InputMove::Start,
+			BuiltinAction::EndOfLine => self.input_move = InputMove::End,
+			_ => return RedrawP::No
+		}
+		RedrawP::Yes
+	}
+
+	pub fn text(&self) -> &str {
+		&self.input
+	}
+
 	fn send_input(&self) {
 		self.out_tx
 			.send(InputOut::Input(self.input.clone()))
@@ -112,6 +129,22 @@ impl Input {
 
 impl Component for Input {
 	type MsgIn = InputIn;
+
+	fn handle_msg(&mut self, msg: Self::MsgIn) {
+		match msg {
+			InputIn::Clear => {
+				self.input.clear();
+				self.cursor_position = 0;
+				self.show_start = 0;
+				self.input_move = InputMove::Nil;
+				self.send_input();
+			}
+			InputIn::Event(ev) => {
+				self.handle_event(ev);
+			}
+		}
+	}
+
 	fn draw(
 		&mut self,
 		f: &mut ratatui::Frame,
@@ -160,41 +193,18 @@ impl Component for Input {
 
 	fn handle_event(&mut self, event: Event) -> (RedrawP, ConsumeP) {
 		match event {
-			Event::Key(key) => {
-				/* 				if key.modifiers != KeyModifiers::NONE || key.modifiers != KeyModifiers::SHIFT {
-					return false;
-				} */
-
-				match key.code {
-					crossterm::event::KeyCode::Backspace => {
-						self.delete_char();
-						(RedrawP::Yes, ConsumeP::Yes)
-					}
-					crossterm::event::KeyCode::Left => {
-						self.move_cursor_left();
-
-						(RedrawP::Yes, ConsumeP::Yes)
-					}
-					crossterm::event::KeyCode::Right => {
-						self.move_cursor_right();
-
-						(RedrawP::Yes, ConsumeP::Yes)
-					}
-					crossterm::event::KeyCode::Home => {
-						self.input_move = InputMove::Start;
-						(RedrawP::Yes, ConsumeP::Yes)
-					}
-					crossterm::event::KeyCode::End => {
-						self.input_move = InputMove::End;
-						(RedrawP::Yes, ConsumeP::Yes)
-					}
-					crossterm::event::KeyCode::Char(c) => {
-						self.enter_char(c);
-						(RedrawP::Yes, ConsumeP::Yes)
-					}
-					_ => (RedrawP::No, ConsumeP::No)
+			// everything else goes through the keymap, see `handle_action`
+			Event::Key(key) => match key.code {
+				crossterm::event::KeyCode::Char(c)
+					if !key
+						.modifiers
+						.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) =>
+				{
+					self.enter_char(c);
+					(RedrawP::Yes, ConsumeP::Yes)
 				}
-			}
+				_ => (RedrawP::No, ConsumeP::No)
+			},
 			_ => (RedrawP::No, ConsumeP::No)
 		}
 	}
@@ -0,0 +1,346 @@
+use std::{
+	fs::File,
+	io::{BufReader, Read},
+	path::Path,
+	sync::{
+		atomic::{AtomicUsize, Ordering},
+		Arc
+	},
+	time::{SystemTime, UNIX_EPOCH}
+};
+
+use chin_tools::wrapper::anyhow::AResult;
+use ratatui::{
+	layout::Rect,
+	style::{Color, Modifier, Style},
+	text::{Line, Span, Text},
+	widgets::Paragraph,
+	Frame
+};
+
+use super::{format_timestamp, Scroll, ViewMsg, ViewType, Viewer};
+use crate::fileinfo::FileInfo;
+
+/// Entries past this are only counted
+const MAX_ENTRIES: usize = 5000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+	Zip,
+	Tar,
+	TarGz,
+	TarZst,
+	SevenZ
+}
+
+impl ArchiveKind {
+	/// libmagic's description decides, the name only tells a compressed tar from a
+	/// compressed single file
+	pub fn detect(path: &Path, desc: Option<&str>) -> Option<Self> {
+		let name = path.file_name()?.to_string_lossy().to_lowercase();
+		let desc = desc.unwrap_or_default();
+		let tar_name = |exts: &[&str]| exts.iter().any(|ext| name.ends_with(ext));
+
+		if desc.contains("Zip archive") || desc.contains("Java archive") {
+			Some(Self::Zip)
+		} else if desc.contains("7-zip archive") {
+			Some(Self::SevenZ)
+		} else if desc.contains("tar archive") {
+			Some(Self::Tar)
+		} else if desc.contains("gzip compressed") && tar_name(&[".tar.gz", ".tgz"]) {
+			Some(Self::TarGz)
+		} else if desc.contains("Zstandard compressed") && tar_name(&[".tar.zst", ".tzst"]) {
+			Some(Self::TarZst)
+		} else if desc.is_empty() {
+			// no libmagic, the extension is all there is
+			match name.rsplit_once('.')?.1 {
+				"zip" | "jar" => Some(Self::Zip),
+				"7z" => Some(Self::SevenZ),
+				"tar" => Some(Self::Tar),
+				_ if tar_name(&[".tar.gz", ".tgz"]) => Some(Self::TarGz),
+				_ if tar_name(&[".tar.zst", ".tzst"]) => Some(Self::TarZst),
+				_ => None
+			}
+		} else {
+			None
+		}
+	}
+}
+
+struct ArchiveEntry {
+	path: String,
+	is_dir: bool,
+	size: u64,
+	mode: Option<u32>,
+	mtime: Option<String>
+}
+
+impl ArchiveEntry {
+	fn line(&self) -> Line<'static> {
+		let name_style = if self.is_dir {
+			Style::new().fg(Color::Blue).add_modifier(Modifier::BOLD)
+		} else {
+			Style::new()
+		};
+		let size = if self.is_dir {
+			"-".to_string()
+		} else {
+			human_bytes::human_bytes(self.size as f64)
+		};
+
+		Line::from(vec![
+			Span::styled(
+				format!("{} ", mode_string(self.mode, self.is_dir)),
+				Style::new().fg(Color::DarkGray)
+			),
+			Span::styled(format!("{:>10} ", size), Style::new().fg(Color::Green)),
+			Span::styled(
+				format!("{:19} ", self.mtime.as_deref().unwrap_or("-")),
+				Style::new().fg(Color::DarkGray)
+			),
+			Span::styled(self.path.clone(), name_style),
+		])
+	}
+}
+
+/// `drwxr-xr-x` like ls, dashes when the archive keeps no unix mode
+fn mode_string(mode: Option<u32>, is_dir: bool) -> String {
+	let kind = if is_dir { 'd' } else { '-' };
+	let Some(mode) = mode else {
+		return format!("{}---------", kind);
+	};
+
+	std::iter::once(kind)
+		.chain((0..9).map(|i| {
+			let bit = 1 << (8 - i);
+			match (mode & bit != 0, i % 3) {
+				(false, _) => '-',
+				(true, 0) => 'r',
+				(true, 1) => 'w',
+				(true, _) => 'x'
+			}
+		}))
+		.collect()
+}
+
+/// Reads the entries of an archive, `None` once the ticket moved on
+struct Lister<'a> {
+	entries: Vec<ArchiveEntry>,
+	count: usize,
+	unpacked: u64,
+	ticket: usize,
+	ticket_holder: &'a AtomicUsize
+}
+
+impl Lister<'_> {
+	fn cancelled(&self) -> bool {
+		self.ticket != self.ticket_holder.load(Ordering::Relaxed)
+	}
+
+	fn push(&mut self, entry: ArchiveEntry) {
+		self.count += 1;
+		self.unpacked += entry.size;
+		if self.entries.len() < MAX_ENTRIES {
+			self.entries.push(entry);
+		}
+	}
+
+	fn list(mut self, path: &Path, kind: ArchiveKind) -> AResult<Option<Self>> {
+		let file = File::open(path)?;
+		match kind {
+			ArchiveKind::Zip => self.list_zip(file)?,
+			ArchiveKind::Tar => self.list_tar(BufReader::new(file))?,
+			ArchiveKind::TarGz => self.list_tar(flate2::read::GzDecoder::new(BufReader::new(file)))?,
+			ArchiveKind::TarZst => self.list_tar(zstd::stream::read::Decoder::new(file)?)?,
+			ArchiveKind::SevenZ => self.list_7z(file)?
+		}
+
+		Ok((!self.cancelled()).then_some(self))
+	}
+
+	fn list_zip(&mut self, file: File) -> AResult<()> {
+		// the central directory has everything, nothing gets decompressed
+		let mut archive = zip::ZipArchive::new(BufReader::new(file))?;
+		for i in 0..archive.len() {
+			if self.cancelled() {
+				break;
+			}
+			let entry = archive.by_index_raw(i)?;
+			let mtime = entry.last_modified().map(|t| {
+				format!(
+					"{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+					t.year(),
+					t.month(),
+					t.day(),
+					t.hour(),
+					t.minute(),
+					t.second()
+				)
+			});
+			self.push(ArchiveEntry {
+				path: entry.name().to_string(),
+				is_dir: entry.is_dir(),
+				size: entry.size(),
+				mode: entry.unix_mode(),
+				mtime
+			});
+		}
+		Ok(())
+	}
+
+	/// A compressed tar has no index, the whole stream is read to count its entries
+	fn list_tar<R: Read>(&mut self, reader: R) -> AResult<()> {
+		let mut archive = tar::Archive::new(reader);
+		for entry in archive.entries()? {
+			if self.cancelled() {
+				break;
+			}
+			let entry = entry?;
+			let header = entry.header();
+			self.push(ArchiveEntry {
+				path: entry.path()?.to_string_lossy().to_string(),
+				is_dir: header.entry_type().is_dir(),
+				size: header.size().unwrap_or(0),
+				mode: header.mode().ok(),
+				mtime: header
+					.mtime()
+					.ok()
+					.and_then(|secs| format_timestamp(secs as i64))
+			});
+		}
+		Ok(())
+	}
+
+	fn list_7z(&mut self, mut file: File) -> AResult<()> {
+		let len = file.metadata()?.len();
+		let archive = sevenz_rust::Archive::read(&mut file, len, &[])?;
+		for entry in archive.files.iter() {
+			if self.cancelled() {
+				break;
+			}
+			let mtime = entry
+				.has_last_modified_date
+				.then(|| SystemTime::from(entry.last_modified_date))
+				.and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+				.and_then(|d| format_timestamp(d.as_secs() as i64));
+			self.push(ArchiveEntry {
+				path: entry.name.clone(),
+				is_dir: entry.is_directory,
+				size: entry.size,
+				mode: None,
+				mtime
+			});
+		}
+		Ok(())
+	}
+}
+
+pub struct ArchiveViewer {}
+
+impl ArchiveViewer {
+	pub fn new() -> Self {
+		Self {}
+	}
+}
+
+impl Viewer for ArchiveViewer {
+	fn reset(&mut self) {}
+
+	async fn handle_fileinfo(
+		&self,
+		fileinfo: FileInfo,
+		ticket: usize,
+		ticket_holder: Arc<AtomicUsize>,
+		_text: Option<String>
+	) -> Option<ViewMsg> {
+		let kind = ArchiveKind::detect(fileinfo.path(), fileinfo.desc.as_deref())?;
+		let path = fileinfo.path().to_path_buf();
+		let holder = ticket_holder.clone();
+		let lister = tokio::task::spawn_blocking(move || {
+			Lister {
+				entries: vec![],
+				count: 0,
+				unpacked: 0,
+				ticket,
+				ticket_holder: &holder
+			}
+			.list(&path, kind)
+			.map(|lister| lister.map(|lister| (lister.entries, lister.count, lister.unpacked)))
+		})
+		.await
+		.ok()?;
+
+		let lines = match lister {
+			Ok(Some((entries, count, unpacked))) => {
+				let mut lines = vec![Line::styled(
+					format!(
+						"{} entries, {} unpacked",
+						count,
+						human_bytes::human_bytes(unpacked as f64)
+					),
+					Style::new().add_modifier(Modifier::ITALIC)
+				)];
+				lines.extend(entries.iter().map(ArchiveEntry::line));
+				if count > entries.len() {
+					lines.push(Line::raw(format!("... {} more", count - entries.len())));
+				}
+				lines
+			}
+			Ok(None) => return None,
+			Err(err) => vec![Line::styled(
+				format!("unable to list the archive: {}", err),
+				Style::new().fg(Color::Red)
+			)]
+		};
+
+		if ticket != ticket_holder.load(Ordering::Relaxed) {
+			return None;
+		}
+
+		let attrs = Self::attrs(&fileinfo);
+		Some(ViewMsg {
+			fileinfo,
+			body: ViewType::Archive(Paragraph::new(Text::from(lines))),
+			attr: attrs
+		})
+	}
+
+	fn handle_event(&mut self, _event: crossterm::event::Event) {}
+
+	fn draw(&self, view_msg: &ViewMsg, scroll: Scroll, f: &mut Frame, rect: &Rect) {
+		if let ViewType::Archive(listing) = &view_msg.body {
+			super::draw_with_attrs(f, rect, listing.clone(), scroll, view_msg.attr.as_ref());
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use rstest::*;
+
+	use super::*;
+
+	#[rstest]
+	#[case::zip("a.zip", Some("Zip archive data, at least v2.0 to extract"), Some(ArchiveKind::Zip))]
+	#[case::jar("a.jar", Some("Java archive data (JAR)"), Some(ArchiveKind::Zip))]
+	#[case::tar_gz("a.tar.gz", Some("gzip compressed data, from Unix"), Some(ArchiveKind::TarGz))]
+	#[case::plain_gz("a.log.gz", Some("gzip compressed data, from Unix"), None)]
+	#[case::tar_zst("a.tar.zst", Some("Zstandard compressed data (v0.8+)"), Some(ArchiveKind::TarZst))]
+	#[case::no_magic("a.7z", None, Some(ArchiveKind::SevenZ))]
+	#[case::text("a.zip", Some("ASCII text"), None)]
+	fn detect(
+		#[case] name: &str,
+		#[case] desc: Option<&str>,
+		#[case] kind: Option<ArchiveKind>
+	) {
+		assert_eq!(kind, ArchiveKind::detect(Path::new(name), desc));
+	}
+
+	#[rstest]
+	#[case(Some(0o755), true, "drwxr-xr-x")]
+	#[case(Some(0o644), false, "-rw-r--r--")]
+	#[case(None, false, "----------")]
+	fn mode(#[case] mode: Option<u32>, #[case] is_dir: bool, #[case] expected: &str) {
+		assert_eq!(expected, mode_string(mode, is_dir));
+	}
+}
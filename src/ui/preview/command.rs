@@ -0,0 +1,189 @@
+use std::{
+	sync::{
+		atomic::{AtomicUsize, Ordering},
+		Arc
+	},
+	time::Duration
+};
+
+use ratatui::{
+	layout::Rect,
+	style::{Color, Modifier, Style},
+	text::Line,
+	widgets::Paragraph,
+	Frame
+};
+use tokio::{
+	io::{AsyncRead, AsyncReadExt},
+	process::Command
+};
+
+use super::{Scroll, ViewMsg, ViewType, Viewer};
+use crate::{ansi, command::ProcessGroup, fileinfo::FileInfo};
+
+/// Output past this is cut, the command is killed when it goes on
+const MAX_OUTPUT_BYTES: usize = 4 * 1024 * 1024;
+/// Errors past this are read but dropped
+const MAX_ERROR_BYTES: usize = 16 * 1024;
+/// How often a running command checks whether the selection moved on
+const TICKET_POLL: Duration = Duration::from_millis(30);
+
+/// Reads `reader` into `buf` up to `cap` bytes, true when there was more. Without `drain`
+/// it stops right there, with it the rest is read to the end and thrown away
+async fn read_capped(
+	reader: &mut (impl AsyncRead + Unpin),
+	buf: &mut Vec<u8>,
+	cap: usize,
+	drain: bool
+) -> std::io::Result<bool> {
+	let mut chunk = [0; 8192];
+	let mut cut = false;
+	loop {
+		let read = reader.read(&mut chunk).await?;
+		if read == 0 {
+			return Ok(cut);
+		}
+		let room = cap.saturating_sub(buf.len());
+		buf.extend_from_slice(&chunk[..read.min(room)]);
+		if read > room {
+			cut = true;
+			if !drain {
+				return Ok(true);
+			}
+		}
+	}
+}
+
+fn error_line(msg: String) -> Line<'static> {
+	Line::styled(msg, Style::new().fg(Color::Red))
+}
+
+pub struct CommandViewer {
+	timeout: Duration
+}
+
+impl CommandViewer {
+	pub fn new(timeout_ms: u64) -> Self {
+		Self {
+			timeout: Duration::from_millis(timeout_ms)
+		}
+	}
+
+	/// Runs a command built in [`crate::command::ExecMode::Capture`] and shows its colored
+	/// output. It is killed once the ticket moves on or it runs out of time
+	pub async fn run(
+		&self,
+		fileinfo: FileInfo,
+		mut command: Command,
+		ticket: usize,
+		ticket_holder: Arc<AtomicUsize>
+	) -> Option<ViewMsg> {
+		let mut out = vec![];
+		let mut errors = vec![];
+		let mut truncated = false;
+
+		let status = match command.spawn() {
+			Ok(mut child) => {
+				// whatever the command started goes with it, however this ends
+				let mut group = ProcessGroup::of(&child);
+				let mut stdout = child.stdout.take()?;
+				let mut stderr = child.stderr.take()?;
+				let finish = async {
+					// both pipes at once, a full one would block the command otherwise
+					let read_output = async {
+						truncated =
+							read_capped(&mut stdout, &mut out, MAX_OUTPUT_BYTES, false).await?;
+						// nobody reads stdout anymore, stderr ends once the command is gone
+						if let (true, Some(group)) = (truncated, group.as_ref()) {
+							group.kill();
+						}
+						Ok::<_, std::io::Error>(())
+					};
+					tokio::try_join!(
+						read_output,
+						read_capped(&mut stderr, &mut errors, MAX_ERROR_BYTES, true)
+					)?;
+					child.wait().await
+				};
+				let stale = async {
+					while ticket == ticket_holder.load(Ordering::Relaxed) {
+						tokio::time::sleep(TICKET_POLL).await;
+					}
+				};
+
+				let status = tokio::select! {
+					status = finish => Some(status),
+					// dropping the group kills it
+					_ = stale => return None,
+					_ = tokio::time::sleep(self.timeout) => None
+				};
+				if let (Some(Ok(_)), Some(group)) = (&status, group.as_mut()) {
+					group.set_reaped();
+				}
+				status
+			}
+			Err(err) => Some(Err(err))
+		};
+
+		let mut lines = ansi::parse_text(&String::from_utf8_lossy(&out)).lines;
+		if truncated {
+			lines.push(Line::styled(
+				"... output truncated",
+				Style::new().add_modifier(Modifier::ITALIC)
+			));
+		}
+		match status {
+			Some(Ok(status)) if status.success() || truncated => {}
+			Some(Ok(status)) => {
+				lines.push(error_line(format!("preview command exited with {}", status)));
+				lines.extend(
+					String::from_utf8_lossy(&errors)
+						.lines()
+						.map(|line| error_line(line.to_string()))
+				);
+			}
+			Some(Err(err)) => lines.push(error_line(format!(
+				"unable to run the preview command: {}",
+				err
+			))),
+			None => lines.push(error_line(format!(
+				"preview command timed out after {:?}",
+				self.timeout
+			)))
+		}
+
+		if ticket != ticket_holder.load(Ordering::Relaxed) {
+			return None;
+		}
+
+		let attrs = Self::attrs(&fileinfo);
+		Some(ViewMsg {
+			fileinfo,
+			body: ViewType::Command(Paragraph::new(lines)),
+			attr: attrs
+		})
+	}
+}
+
+impl Viewer for CommandViewer {
+	fn reset(&mut self) {}
+
+	/// Nothing to preview without the command, see [`CommandViewer::run`]
+	async fn handle_fileinfo(
+		&self,
+		_fileinfo: FileInfo,
+		_ticket: usize,
+		_ticket_holder: Arc<AtomicUsize>,
+		_text: Option<String>
+	) -> Option<ViewMsg> {
+		None
+	}
+
+	fn handle_event(&mut self, _event: crossterm::event::Event) {}
+
+	fn draw(&self, view_msg: &ViewMsg, scroll: Scroll, f: &mut Frame, rect: &Rect) {
+		if let ViewType::Command(output) = &view_msg.body {
+			super::draw_with_attrs(f, rect, output.clone(), scroll, view_msg.attr.as_ref());
+		}
+	}
+}
@@ -0,0 +1,165 @@
+use std::{
+	cmp::Ordering as CmpOrdering,
+	fs::Metadata,
+	path::PathBuf,
+	sync::{
+		atomic::{AtomicUsize, Ordering},
+		Arc
+	}
+};
+
+use ratatui::{
+	layout::Rect,
+	style::{Color, Modifier, Style},
+	text::{Line, Span, Text},
+	widgets::Paragraph,
+	Frame
+};
+
+use super::{format_mtime, Scroll, ViewMsg, ViewType, Viewer};
+use crate::fileinfo::FileInfo;
+
+/// Listing more than this is pointless in a preview, the rest is only counted
+const MAX_ENTRIES: usize = 5000;
+
+struct DirEntry {
+	name: String,
+	path: PathBuf,
+	/// Links to a directory count as one
+	is_dir: bool,
+	/// Only read for the listed entries
+	metadata: Option<Metadata>
+}
+
+impl DirEntry {
+	fn is_hidden(&self) -> bool {
+		self.name.starts_with('.')
+	}
+
+	fn line(&self, name_width: usize) -> Line<'static> {
+		let (name, name_style) = if self.is_dir {
+			(
+				format!("{}/", self.name),
+				Style::new().fg(Color::Blue).add_modifier(Modifier::BOLD)
+			)
+		} else {
+			(self.name.clone(), Style::new())
+		};
+		let name_style = if self.is_hidden() {
+			name_style.add_modifier(Modifier::DIM)
+		} else {
+			name_style
+		};
+
+		let size = match self.metadata.as_ref() {
+			Some(md) if !self.is_dir => human_bytes::human_bytes(md.len() as f64),
+			_ => "-".to_string()
+		};
+		let mtime = self
+			.metadata
+			.as_ref()
+			.and_then(format_mtime)
+			.unwrap_or_default();
+
+		Line::from(vec![
+			Span::styled(format!("{:<width$}", name, width = name_width), name_style),
+			Span::styled(format!(" {:>10} ", size), Style::new().fg(Color::Green)),
+			Span::styled(mtime, Style::new().fg(Color::DarkGray)),
+		])
+	}
+}
+
+pub struct DirViewer {}
+
+impl DirViewer {
+	pub fn new() -> Self {
+		Self {}
+	}
+
+	async fn read_entries(fileinfo: &FileInfo) -> std::io::Result<Vec<DirEntry>> {
+		let mut dir = tokio::fs::read_dir(fileinfo.path()).await?;
+		let mut entries = vec![];
+		while let Some(en) = dir.next_entry().await? {
+			// the file type mostly comes with the entry, only links need a stat
+			let is_dir = match en.file_type().await {
+				Ok(ft) if ft.is_symlink() => tokio::fs::metadata(en.path())
+					.await
+					.map_or(false, |md| md.is_dir()),
+				Ok(ft) => ft.is_dir(),
+				Err(_) => false
+			};
+			entries.push(DirEntry {
+				name: en.file_name().to_string_lossy().to_string(),
+				path: en.path(),
+				is_dir,
+				metadata: None
+			});
+		}
+
+		entries.sort_by(|a, b| match (a.is_dir, b.is_dir) {
+			(true, false) => CmpOrdering::Less,
+			(false, true) => CmpOrdering::Greater,
+			_ => a.name.cmp(&b.name)
+		});
+
+		for en in entries.iter_mut().take(MAX_ENTRIES) {
+			en.metadata = tokio::fs::metadata(&en.path).await.ok();
+		}
+
+		Ok(entries)
+	}
+}
+
+impl Viewer for DirViewer {
+	fn reset(&mut self) {}
+
+	async fn handle_fileinfo(
+		&self,
+		fileinfo: FileInfo,
+		ticket: usize,
+		ticket_holder: Arc<AtomicUsize>,
+		_text: Option<String>
+	) -> Option<ViewMsg> {
+		let entries = Self::read_entries(&fileinfo).await.ok()?;
+		if ticket != ticket_holder.load(Ordering::Relaxed) {
+			return None;
+		}
+
+		let hidden = entries.iter().filter(|en| en.is_hidden()).count();
+		let name_width = entries
+			.iter()
+			.take(MAX_ENTRIES)
+			.map(|en| en.name.chars().count() + usize::from(en.is_dir))
+			.max()
+			.unwrap_or(0)
+			.clamp(8, 40);
+
+		let mut lines = vec![Line::styled(
+			format!("{} entries, {} hidden", entries.len(), hidden),
+			Style::new().add_modifier(Modifier::ITALIC)
+		)];
+		lines.extend(entries.iter().take(MAX_ENTRIES).map(|en| en.line(name_width)));
+		if entries.len() > MAX_ENTRIES {
+			lines.push(Line::raw(format!(
+				"... {} more",
+				entries.len() - MAX_ENTRIES
+			)));
+		}
+
+		let attrs = Self::attrs(&fileinfo);
+
+		Some(ViewMsg {
+			fileinfo,
+			body: ViewType::Directory(Paragraph::new(Text::from(lines))),
+			attr: attrs
+		})
+	}
+
+	fn handle_event(&mut self, _event: crossterm::event::Event) {}
+
+	fn draw(&self, view_msg: &ViewMsg, scroll: Scroll, f: &mut Frame, rect: &Rect) {
+		if let ViewType::Directory(listing) = &view_msg.body {
+			super::draw_with_attrs(f, rect, listing.clone(), scroll, view_msg.attr.as_ref());
+		}
+	}
+}
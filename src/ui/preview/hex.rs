@@ -0,0 +1,207 @@
+use std::{
+	io::SeekFrom,
+	sync::{
+		atomic::{AtomicUsize, Ordering},
+		Arc
+	}
+};
+
+use ratatui::{
+	layout::Rect,
+	style::{Color, Style},
+	text::{Line, Span},
+	widgets::Paragraph,
+	Frame
+};
+use tokio::{
+	fs::File,
+	io::{AsyncReadExt, AsyncSeekExt}
+};
+
+use super::{Scroll, ViewMsg, ViewType, Viewer};
+use crate::fileinfo::FileInfo;
+
+pub const BYTES_PER_ROW: usize = 16;
+/// Bytes read around the viewport, 4096 rows
+const WINDOW_BYTES: u64 = 64 * 1024;
+/// `00000010: 0a 0b ... |................|` is this wide
+const ROW_WIDTH: usize = 10 + BYTES_PER_ROW * 3 + 1 + 2 + BYTES_PER_ROW;
+
+/// Byte classes are colored like hexyl does
+fn byte_style(b: u8) -> Style {
+	let color = match b {
+		0 => Color::DarkGray,
+		b if b.is_ascii_graphic() => Color::Cyan,
+		b if b.is_ascii_whitespace() => Color::Green,
+		b if b.is_ascii() => Color::Magenta,
+		_ => Color::Yellow
+	};
+	Style::new().fg(color)
+}
+
+fn byte_char(b: u8) -> char {
+	if b.is_ascii_graphic() || b == b' ' {
+		b as char
+	} else {
+		'.'
+	}
+}
+
+/// The bytes `offset..offset + bytes.len()` of a file that is `size` long
+pub struct HexDump {
+	pub offset: u64,
+	pub bytes: Vec<u8>,
+	pub size: u64
+}
+
+impl HexDump {
+	pub fn rows(&self) -> usize {
+		self.size.div_ceil(BYTES_PER_ROW as u64) as usize
+	}
+
+	pub fn width(&self) -> usize {
+		ROW_WIDTH
+	}
+
+	fn first_row(&self) -> usize {
+		(self.offset / BYTES_PER_ROW as u64) as usize
+	}
+
+	/// Whether the rows `first..first + height` need bytes that were not read
+	pub fn misses(&self, first: usize, height: usize) -> bool {
+		let end = self.offset + self.bytes.len() as u64;
+		let loaded_rows = self.bytes.len().div_ceil(BYTES_PER_ROW);
+		first < self.first_row()
+			|| (end < self.size && first + height > self.first_row() + loaded_rows)
+	}
+
+	/// `row` with its offset, hex and ascii columns, `None` outside the window
+	pub fn line(&self, row: usize) -> Option<Line<'static>> {
+		let start = row.checked_sub(self.first_row())? * BYTES_PER_ROW;
+		if start >= self.bytes.len() {
+			return None;
+		}
+		let bytes = &self.bytes[start..(start + BYTES_PER_ROW).min(self.bytes.len())];
+
+		let mut spans = Vec::with_capacity(BYTES_PER_ROW * 2 + 4);
+		spans.push(Span::styled(
+			format!("{:08x}: ", row * BYTES_PER_ROW),
+			Style::new().fg(Color::DarkGray)
+		));
+		for i in 0..BYTES_PER_ROW {
+			// an extra space splits the row in two halves
+			let sep = if i == BYTES_PER_ROW / 2 - 1 { "  " } else { " " };
+			match bytes.get(i) {
+				Some(b) => spans.push(Span::styled(format!("{:02x}{}", b, sep), byte_style(*b))),
+				None => spans.push(Span::raw(format!("  {}", sep)))
+			}
+		}
+		spans.push(Span::raw("|"));
+		spans.extend(
+			bytes
+				.iter()
+				.map(|b| Span::styled(byte_char(*b).to_string(), byte_style(*b)))
+		);
+		spans.push(Span::raw("|"));
+
+		Some(Line::from(spans))
+	}
+}
+
+pub struct HexViewer {}
+
+impl HexViewer {
+	pub fn new() -> Self {
+		Self {}
+	}
+
+	/// Reads the window of `fileinfo` starting at `row`
+	pub async fn load(
+		&self,
+		fileinfo: FileInfo,
+		row: usize,
+		ticket: usize,
+		ticket_holder: Arc<AtomicUsize>
+	) -> Option<ViewMsg> {
+		let offset = (row * BYTES_PER_ROW) as u64;
+		let mut file = File::open(fileinfo.path()).await.ok()?;
+		let size = file.metadata().await.ok()?.len();
+		file.seek(SeekFrom::Start(offset)).await.ok()?;
+
+		let mut bytes = vec![];
+		file.take(WINDOW_BYTES).read_to_end(&mut bytes).await.ok()?;
+
+		if ticket != ticket_holder.load(Ordering::Relaxed) {
+			return None;
+		}
+
+		let attrs = Self::attrs(&fileinfo);
+		Some(ViewMsg {
+			fileinfo,
+			body: ViewType::Hex(HexDump {
+				offset,
+				bytes,
+				size
+			}),
+			attr: attrs
+		})
+	}
+}
+
+impl Viewer for HexViewer {
+	fn reset(&mut self) {}
+
+	async fn handle_fileinfo(
+		&self,
+		fileinfo: FileInfo,
+		ticket: usize,
+		ticket_holder: Arc<AtomicUsize>,
+		_text: Option<String>
+	) -> Option<ViewMsg> {
+		self.load(fileinfo, 0, ticket, ticket_holder).await
+	}
+
+	fn handle_event(&mut self, _event: crossterm::event::Event) {}
+
+	fn draw(&self, view_msg: &ViewMsg, scroll: Scroll, f: &mut Frame, rect: &Rect) {
+		if let ViewType::Hex(dump) = &view_msg.body {
+			// rows are formatted as they are shown, so the offsets follow the scroll
+			let visible = (scroll.line..scroll.line + rect.height as usize)
+				.map_while(|row| dump.line(row))
+				.collect::<Vec<_>>();
+			let scroll = Scroll {
+				line: 0,
+				..scroll
+			};
+			super::draw_with_attrs(
+				f,
+				rect,
+				Paragraph::new(visible),
+				scroll,
+				view_msg.attr.as_ref()
+			);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn format_row() {
+		let dump = HexDump {
+			offset: 16,
+			bytes: b"Hello,\x00world!\n\xff".to_vec(),
+			size: 32
+		};
+		let line = dump.line(1).unwrap().to_string();
+		assert_eq!(
+			"00000010: 48 65 6c 6c 6f 2c 00 77  6f 72 6c 64 21 0a ff    |Hello,.world!..|",
+			line
+		);
+		assert_eq!(ROW_WIDTH, line.chars().count() + 1);
+		assert!(dump.line(0).is_none());
+		assert!(dump.line(2).is_none());
+	}
+}
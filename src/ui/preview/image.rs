@@ -0,0 +1,126 @@
+use std::{
+	num::NonZeroUsize,
+	path::{Path, PathBuf},
+	sync::{
+		atomic::{AtomicUsize, Ordering},
+		Arc, Mutex
+	}
+};
+
+use image::ImageFormat;
+use lru::LruCache;
+use ratatui::{layout::Rect, Frame};
+use ratatui_image::{picker::Picker, protocol::Protocol, Image, Resize};
+
+use super::{Scroll, ViewMsg, ViewType, Viewer};
+use crate::fileinfo::FileInfo;
+
+/// Encoded images kept around, the least recently shown goes first
+const MAX_CACHED: usize = 32;
+
+type CacheKey = (PathBuf, u16, u16);
+
+pub fn is_image(path: &Path) -> bool {
+	matches!(
+		ImageFormat::from_path(path),
+		Ok(ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::Gif | ImageFormat::WebP)
+	)
+}
+
+pub struct ImageViewer {
+	/// Picks kitty/sixel/iterm2 when the terminal supports them, halfblocks otherwise
+	picker: Picker,
+	/// Where the image body was drawn last, images are resized to fit it
+	area: Mutex<Rect>,
+	cache: Mutex<LruCache<CacheKey, Arc<dyn Protocol>>>
+}
+
+impl ImageViewer {
+	pub fn new(picker: Picker) -> Self {
+		Self {
+			picker,
+			area: Mutex::new(Rect::default()),
+			cache: Mutex::new(LruCache::new(NonZeroUsize::new(MAX_CACHED).unwrap()))
+		}
+	}
+
+	pub fn set_area(&self, area: Rect) {
+		*self.area.lock().unwrap() = area;
+	}
+
+	pub fn area(&self) -> Rect {
+		*self.area.lock().unwrap()
+	}
+}
+
+impl Viewer for ImageViewer {
+	fn reset(&mut self) {
+		self.cache.lock().unwrap().clear();
+	}
+
+	async fn handle_fileinfo(
+		&self,
+		fileinfo: FileInfo,
+		ticket: usize,
+		ticket_holder: Arc<AtomicUsize>,
+		_text: Option<String>
+	) -> Option<ViewMsg> {
+		let attrs = Self::attrs(&fileinfo);
+		let (area, _) = super::split_attrs(&self.area(), attrs.as_ref());
+		if area.width == 0 || area.height == 0 {
+			return None;
+		}
+
+		let key = (fileinfo.path().to_path_buf(), area.width, area.height);
+		let cached = self.cache.lock().unwrap().get(&key).cloned();
+		let protocol = match cached {
+			Some(protocol) => protocol,
+			None => {
+				let mut picker = self.picker;
+				let path = key.0.clone();
+				let holder = ticket_holder.clone();
+				// decoding a large image takes a while, skip it if the user moved on meanwhile
+				let protocol = tokio::task::spawn_blocking(move || {
+					if ticket != holder.load(Ordering::Relaxed) {
+						return None;
+					}
+					let img = image::open(&path).ok()?;
+					if ticket != holder.load(Ordering::Relaxed) {
+						return None;
+					}
+					picker.new_protocol(img, area, Resize::Fit(None)).ok()
+				})
+				.await
+				.ok()??;
+
+				let protocol: Arc<dyn Protocol> = Arc::from(protocol);
+				self.cache.lock().unwrap().put(key, protocol.clone());
+				protocol
+			}
+		};
+
+		if ticket != ticket_holder.load(Ordering::Relaxed) {
+			return None;
+		}
+
+		Some(ViewMsg {
+			fileinfo,
+			body: ViewType::Image(protocol, area),
+			attr: attrs
+		})
+	}
+
+	fn handle_event(&mut self, _event: crossterm::event::Event) {}
+
+	fn draw(&self, view_msg: &ViewMsg, _scroll: Scroll, f: &mut Frame, rect: &Rect) {
+		let (body, attrs_area) = super::split_attrs(rect, view_msg.attr.as_ref());
+
+		if let ViewType::Image(protocol, _) = &view_msg.body {
+			f.render_widget(Image::new(protocol.as_ref()), body);
+		}
+		view_msg
+			.attr
+			.as_ref()
+			.map(|e| f.render_widget(e.clone(), attrs_area));
+	}
+}
@@ -1,38 +1,87 @@
 use std::{
+	fs::Metadata,
 	os::unix::fs::MetadataExt,
+	path::Path,
 	sync::{
 		atomic::{AtomicUsize, Ordering},
-		Arc
+		Arc, Mutex
 	}
 };
 
+use archive::{ArchiveKind, ArchiveViewer};
 use chrono::DateTime;
-use crossterm::event::Event;
+use command::CommandViewer;
+use crossterm::event::{Event, KeyModifiers, MouseEvent, MouseEventKind};
+use dir::DirViewer;
 use flume::Sender;
+use hex::{HexDump, HexViewer};
 use magic::{cookie::Load, Cookie};
 use ratatui::{
+	layout::{Constraint, Layout},
 	prelude::Rect,
 	style::Style,
 	text::{Line, Span, Text},
 	widgets::{Paragraph, Wrap},
 	Frame
 };
-use text::TextViewer;
+use ratatui_image::{picker::Picker, protocol::Protocol};
+use text::{LineIndex, TextViewer, TextWindow, WINDOW_LINES};
 
+use self::image::ImageViewer;
 use crate::{
-	dirwalker::read_first_n_chars,
-	fileinfo::{FileInfo, FilePath}
+	command::{CommandTemplate, ExecMode, Placeholders},
+	config::PreviewConfig,
+	dirwalker::file_is_text,
+	fileinfo::{FileInfo, FilePath},
+	keymap::BuiltinAction,
+	ui::RedrawP
 };
 
+pub mod archive;
 pub mod attr;
+pub mod command;
+pub mod dir;
+pub mod hex;
+pub mod image;
 pub mod text;
 
 pub enum ViewType {
-	Text(Paragraph<'static>),
-	Directory,
+	Text(TextWindow),
+	Directory(Paragraph<'static>),
+	/// The encoded image and the area it was resized for
+	Image(Arc<dyn Protocol>, Rect),
+	Hex(HexDump),
+	Archive(Paragraph<'static>),
+	/// The output of a preview command
+	Command(Paragraph<'static>),
 	Unknown
 }
 
+/// Lines moved per mouse wheel notch
+const MOUSE_SCROLL_LINES: isize = 3;
+/// Columns moved per horizontal scroll step
+const SCROLL_COLUMNS: isize = 8;
+
+impl ViewType {
+	/// (lines, widest line) of the body laid out in `width`
+	fn extent(&self, width: u16) -> (usize, usize) {
+		match self {
+			ViewType::Text(window) => (window.total(), window.width),
+			ViewType::Directory(p) | ViewType::Archive(p) | ViewType::Command(p) =>
+				(p.line_count(width), p.line_width()),
+			ViewType::Hex(dump) => (dump.rows(), dump.width()),
+			ViewType::Image(..) | ViewType::Unknown => (0, 0)
+		}
+	}
+}
+
+/// The first line and column of the body shown in the stage
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Scroll {
+	pub line: usize,
+	pub column: usize
+}
+
 pub struct ViewMsg {
 	pub fileinfo: FileInfo,
 	pub body: ViewType,
@@ -40,69 +89,166 @@ pub struct ViewMsg {
 }
 
 pub struct FileViewer {
-	file: Option<(ViewMsg, usize)>,
+	file: Option<(ViewMsg, Scroll)>,
+	/// Height of the body drawn last, one page of scrolling
+	page: u16,
+	wrap: bool,
+	/// The text or hex window requested and not arrived yet, 0 for none. A failed read
+	/// clears it only while no newer window was requested
+	window_pending: Arc<AtomicUsize>,
+	window_requests: usize,
 	text_viewer: Arc<TextViewer>,
-	magic: Option<Arc<Cookie<Load>>>,
+	dir_viewer: Arc<DirViewer>,
+	image_viewer: Arc<ImageViewer>,
+	hex_viewer: Arc<HexViewer>,
+	archive_viewer: Arc<ArchiveViewer>,
+	command_viewer: Arc<CommandViewer>,
+	config: Arc<PreviewConfig>,
+	/// The query and directory substituted into preview commands
+	input: String,
+	cwd: String,
+	magic: Arc<Magic>,
 	ticket: Arc<AtomicUsize>,
 	out_tx: Sender<ViewMsg>
 }
 
 impl FileViewer {
-	pub fn new(out_tx: Sender<ViewMsg>) -> Self {
-		// open a new configuration with flags
-		let cookie = magic::Cookie::open(magic::cookie::Flags::ERROR)
-			.map(|cookie| {
-				// load the system's default database
-				let database = &Default::default();
-				cookie.load(database).ok()
-			})
-			.ok();
-		let cookie = match cookie {
-			Some(e) => e,
-			None => None
-		}
-		.map(|e| Arc::new(e));
-
+	pub fn new(out_tx: Sender<ViewMsg>, config: PreviewConfig, picker: Picker) -> Self {
 		Self {
 			file: None,
-			text_viewer: Arc::new(TextViewer::new()),
-			magic: cookie,
+			page: 0,
+			wrap: config.wrap,
+			window_pending: Arc::new(AtomicUsize::new(0)),
+			window_requests: 0,
+			// the theme was picked by the terminal's background before the tui started
+			text_viewer: Arc::new(TextViewer::new(
+				config.wrap,
+				config.line_numbers,
+				config.theme(true).to_string()
+			)),
+			dir_viewer: Arc::new(DirViewer::new()),
+			image_viewer: Arc::new(ImageViewer::new(picker)),
+			hex_viewer: Arc::new(HexViewer::new()),
+			archive_viewer: Arc::new(ArchiveViewer::new()),
+			command_viewer: Arc::new(CommandViewer::new(config.timeout)),
+			input: String::new(),
+			cwd: String::new(),
+			magic: Arc::new(Magic {
+				desc: open_cookie(magic::cookie::Flags::ERROR),
+				encoding: open_cookie(
+					magic::cookie::Flags::ERROR | magic::cookie::Flags::MIME_ENCODING
+				),
+				mime: match config.wants_mime() {
+					true =>
+						open_cookie(magic::cookie::Flags::ERROR | magic::cookie::Flags::MIME_TYPE),
+					false => None
+				}
+			}),
+			config: Arc::new(config),
 			ticket: Arc::new(AtomicUsize::new(0)),
 			out_tx
 		}
 	}
 
-	pub fn handle_file(&mut self, fileinfo: &FilePath) {
-		if self
+	/// `input` and `cwd` are substituted into a preview command
+	pub fn handle_file(&mut self, fileinfo: &FilePath, input: &str, cwd: &str) {
+		self.input = input.to_string();
+		self.cwd = cwd.to_string();
+		let page = self.page;
+		if let Some((msg, scroll)) = self
 			.file
-			.as_ref()
-			.map_or(false, |e| e.0.fileinfo.path() == fileinfo.path())
+			.as_mut()
+			.filter(|(msg, _)| msg.fileinfo.path() == fileinfo.path())
 		{
+			// another grep result in the same file, only the target line moves
+			if msg.fileinfo.path.position() != fileinfo.position() {
+				msg.fileinfo.path = fileinfo.clone();
+				*scroll = target_scroll(fileinfo, page);
+			}
 			return;
 		} else {
 			self.file.take();
 		}
 
-		let mut fileinfo: FileInfo = fileinfo.clone().into();
+		self.load(fileinfo);
+	}
 
-		let magic = self.magic.clone();
-		magic.map(|m| fileinfo.desc = m.file(fileinfo.path()).ok());
+	fn load(&mut self, fileinfo: &FilePath) {
+		let fileinfo: FileInfo = fileinfo.clone().into();
 
+		self.window_pending.store(0, Ordering::Relaxed);
 		let sender = self.out_tx.clone();
 		let ticket_holder = self.ticket.clone();
 		let text_handler = self.text_viewer.clone();
+		let dir_handler = self.dir_viewer.clone();
+		let image_handler = self.image_viewer.clone();
+		let hex_handler = self.hex_viewer.clone();
+		let archive_handler = self.archive_viewer.clone();
+		let command_handler = self.command_viewer.clone();
+		let magic = self.magic.clone();
+		let config = self.config.clone();
+		let input = self.input.clone();
+		let cwd = self.cwd.clone();
+		// a target line far into the file is read first
+		let text_start = target_scroll(&fileinfo.path, self.page)
+			.line
+			.saturating_sub(WINDOW_LINES / 2);
+		// a newer selection makes the pending one stale
+		let ticket = ticket_holder.fetch_add(1, Ordering::Relaxed) + 1;
 
 		tokio::spawn(async move {
-			let ticket = ticket_holder.load(Ordering::Relaxed);
+			if ticket != ticket_holder.load(Ordering::Relaxed) {
+				return;
+			}
 			let mut fileinfo = fileinfo;
 			fileinfo.metadata = fileinfo.path.pathbuf.metadata().map_err(|e| e.to_string());
+			// libmagic reads the file, slow ones would hold up a runtime thread
+			let path = fileinfo.path.pathbuf.clone();
+			let sniffed = tokio::task::spawn_blocking(move || magic.sniff(&path))
+				.await
+				.unwrap_or_default();
+			fileinfo.desc = sniffed.desc;
+			// libmagic calls an empty file binary
+			let empty = fileinfo.metadata.as_ref().map_or(false, |md| md.len() == 0);
+			let binary = sniffed.binary && !empty;
 
-			let msg = match read_first_n_chars(fileinfo.path(), 5000).await {
-				Ok(text) =>
-					text_handler
-						.handle_fileinfo(fileinfo, ticket, ticket_holder, Some(text))
-						.await,
-				Err(_) => None
+			let is_dir = fileinfo.path().is_dir();
+			let preview_command = config
+				.command_for(fileinfo.path(), is_dir, sniffed.mime.as_deref())
+				.map(|template| {
+					CommandTemplate::new(template, ExecMode::Capture).command(&Placeholders {
+						input: &input,
+						selected: std::slice::from_ref(&fileinfo.path),
+						cwd: &cwd
+					})
+				});
+
+			let msg = if let Some(command) = preview_command {
+				command_handler
+					.run(fileinfo, command, ticket, ticket_holder)
+					.await
+			} else if is_dir {
+				dir_handler
+					.handle_fileinfo(fileinfo, ticket, ticket_holder, None)
+					.await
+			} else if self::image::is_image(fileinfo.path()) {
+				image_handler
+					.handle_fileinfo(fileinfo, ticket, ticket_holder, None)
+					.await
+			} else if ArchiveKind::detect(fileinfo.path(), fileinfo.desc.as_deref()).is_some() {
+				archive_handler
+					.handle_fileinfo(fileinfo, ticket, ticket_holder, None)
+					.await
+			} else if binary || !file_is_text(fileinfo.path()).await.unwrap_or(false) {
+				hex_handler
+					.handle_fileinfo(fileinfo, ticket, ticket_holder, None)
+					.await
+			} else {
+				// big files arrive in windows, the stream sends them itself
+				text_handler
+					.stream(fileinfo, text_start, ticket, ticket_holder, sender.clone())
+					.await;
+				None
 			};
 
 			if let Some(msg) = msg {
@@ -111,23 +257,188 @@ impl FileViewer {
 		});
 	}
 
-	pub fn set_view(&mut self, msg: ViewMsg) {
+	pub fn set_view(&mut self, mut msg: ViewMsg) {
 		if self
 			.file
 			.as_ref()
-			.map_or(true, |(m, c)| m.fileinfo.path() == msg.fileinfo.path())
+			.map_or(true, |(m, _)| m.fileinfo.path() == msg.fileinfo.path())
 		{
-			self.file.replace((msg, 0));
+			self.window_pending.store(0, Ordering::Relaxed);
+			// a new view of the same file keeps its position and target line, a new file
+			// starts at its target line
+			let scroll = match self.file.take() {
+				Some((shown, scroll)) => {
+					msg.fileinfo.path = shown.fileinfo.path;
+					scroll
+				}
+				None => target_scroll(&msg.fileinfo.path, self.page)
+			};
+			self.file.replace((msg, scroll));
+		}
+	}
+
+	/// Reads the lines, or hex rows without an `index`, from `start` of the file already
+	/// shown, without cancelling its line count
+	fn load_window(&mut self, fileinfo: FileInfo, start: usize, index: Option<Arc<LineIndex>>) {
+		self.window_requests += 1;
+		let request = self.window_requests;
+		self.window_pending.store(request, Ordering::Relaxed);
+		let pending = self.window_pending.clone();
+
+		let sender = self.out_tx.clone();
+		let ticket_holder = self.ticket.clone();
+		let ticket = ticket_holder.load(Ordering::Relaxed);
+		let text_handler = self.text_viewer.clone();
+		let hex_handler = self.hex_viewer.clone();
+
+		tokio::spawn(async move {
+			let msg = match index {
+				Some(index) => {
+					index.want(start);
+					text_handler
+						.load(fileinfo, start, index, ticket, ticket_holder)
+						.await
+				}
+				None => hex_handler.load(fileinfo, start, ticket, ticket_holder).await
+			};
+			if let Some(msg) = msg {
+				let _ = sender.send(msg);
+			} else {
+				// the next scroll asks again
+				pending
+					.compare_exchange(request, 0, Ordering::Relaxed, Ordering::Relaxed)
+					.ok();
+			}
+		});
+	}
+
+	fn scroll_by(&mut self, lines: isize, columns: isize) -> RedrawP {
+		let wrap = self.wrap;
+		let Some((_, scroll)) = self.file.as_mut() else {
+			return RedrawP::No;
+		};
+		let old = *scroll;
+		scroll.line = scroll.line.saturating_add_signed(lines);
+		// wrapped lines never overflow the stage
+		if !wrap {
+			scroll.column = scroll.column.saturating_add_signed(columns);
+		}
+		// the upper bounds depend on the stage size, `view` clamps them
+		if old == *scroll {
+			RedrawP::No
+		} else {
+			RedrawP::Yes
+		}
+	}
+
+	pub fn handle_action(&mut self, action: BuiltinAction) -> RedrawP {
+		let page = self.page.max(1) as isize;
+		match action {
+			BuiltinAction::PreviewUp => self.scroll_by(-1, 0),
+			BuiltinAction::PreviewDown => self.scroll_by(1, 0),
+			BuiltinAction::PreviewPageUp => self.scroll_by(-page, 0),
+			BuiltinAction::PreviewPageDown => self.scroll_by(page, 0),
+			BuiltinAction::PreviewTop => self.scroll_by(isize::MIN, 0),
+			BuiltinAction::PreviewBottom => self.scroll_by(isize::MAX, 0),
+			BuiltinAction::PreviewLeft => self.scroll_by(0, -SCROLL_COLUMNS),
+			BuiltinAction::PreviewRight => self.scroll_by(0, SCROLL_COLUMNS),
+			BuiltinAction::PreviewLineNumbers => {
+				self.text_viewer.toggle_line_numbers();
+				match self.file.as_ref().map(|(msg, _)| &msg.body) {
+					Some(ViewType::Text(_)) => RedrawP::Yes,
+					_ => RedrawP::No
+				}
+			}
+			_ => RedrawP::No
+		}
+	}
+
+	/// The wheel scrolls the preview while the pointer is over `stage`, shift turns it sideways
+	pub fn handle_mouse(&mut self, event: MouseEvent, stage: &Rect) -> RedrawP {
+		let over_stage = stage.x <= event.column
+			&& event.column < stage.right()
+			&& stage.y <= event.row
+			&& event.row < stage.bottom();
+		if !over_stage {
+			return RedrawP::No;
+		}
+
+		let sideways = event.modifiers.contains(KeyModifiers::SHIFT);
+		match event.kind {
+			MouseEventKind::ScrollUp if sideways => self.scroll_by(0, -SCROLL_COLUMNS),
+			MouseEventKind::ScrollDown if sideways => self.scroll_by(0, SCROLL_COLUMNS),
+			MouseEventKind::ScrollUp => self.scroll_by(-MOUSE_SCROLL_LINES, 0),
+			MouseEventKind::ScrollDown => self.scroll_by(MOUSE_SCROLL_LINES, 0),
+			MouseEventKind::ScrollLeft => self.scroll_by(0, -SCROLL_COLUMNS),
+			MouseEventKind::ScrollRight => self.scroll_by(0, SCROLL_COLUMNS),
+			_ => RedrawP::No
 		}
 	}
 
 	pub fn view(&mut self, frame: &mut Frame, rect: &Rect) {
-		if let Some((msg, cursor)) = self.file.as_ref() {
+		self.image_viewer.set_area(*rect);
+
+		// the stage was resized, the image has to be encoded again for the new size
+		let stale_image = self.file.as_ref().and_then(|(msg, _)| match &msg.body {
+			ViewType::Image(_, area)
+				if *area != split_attrs(rect, msg.attr.as_ref()).0 =>
+				Some(msg.fileinfo.path.clone()),
+			_ => None
+		});
+		if let Some(path) = stale_image {
+			self.file.take();
+			self.load(&path);
+		}
+
+		if let Some((msg, scroll)) = self.file.as_mut() {
+			let (body, _) = split_attrs(rect, msg.attr.as_ref());
+			self.page = body.height;
+
+			let (lines, width) = msg.body.extent(body.width);
+			scroll.line = scroll.line.min(lines.saturating_sub(body.height as usize));
+			scroll.column = scroll.column.min(width.saturating_sub(body.width as usize));
+		}
+
+		// scrolled out of the lines read so far
+		let page = self.page as usize;
+		let missed_window = self.file.as_ref().and_then(|(msg, scroll)| match &msg.body {
+			_ if self.window_pending.load(Ordering::Relaxed) != 0 => None,
+			ViewType::Text(window) if window.misses(scroll.line, page) => Some((
+				msg.fileinfo.clone(),
+				scroll.line.saturating_sub(WINDOW_LINES / 2),
+				Some(window.index.clone())
+			)),
+			ViewType::Hex(dump) if dump.misses(scroll.line, page) => Some((
+				msg.fileinfo.clone(),
+				scroll.line.saturating_sub(page),
+				None
+			)),
+			_ => None
+		});
+		if let Some((fileinfo, start, index)) = missed_window {
+			self.load_window(fileinfo, start, index);
+		}
+
+		if let Some((msg, scroll)) = self.file.as_ref() {
 			match &msg.body {
-				ViewType::Text(text) => {
-					self.text_viewer.draw(msg, *cursor, frame, rect);
+				ViewType::Text(_) => {
+					self.text_viewer.draw(msg, *scroll, frame, rect);
+				}
+				ViewType::Directory(_) => {
+					self.dir_viewer.draw(msg, *scroll, frame, rect);
+				}
+				ViewType::Image(..) => {
+					self.image_viewer.draw(msg, *scroll, frame, rect);
+				}
+				ViewType::Hex(_) => {
+					self.hex_viewer.draw(msg, *scroll, frame, rect);
+				}
+				ViewType::Archive(_) => {
+					self.archive_viewer.draw(msg, *scroll, frame, rect);
+				}
+				ViewType::Command(_) => {
+					self.command_viewer.draw(msg, *scroll, frame, rect);
 				}
-				ViewType::Directory => {}
 				ViewType::Unknown => {}
 			}
 		}
@@ -147,23 +458,21 @@ pub trait Viewer {
 
 	fn handle_event(&mut self, event: Event);
 
-	fn draw(&self, view_msg: &ViewMsg, show_cursor: usize, f: &mut Frame, rect: &Rect);
+	fn draw(&self, view_msg: &ViewMsg, scroll: Scroll, f: &mut Frame, rect: &Rect);
 
 	fn attrs(fi: &FileInfo) -> Option<Paragraph<'static>> {
+		attrs_paragraph(Self::attr_lines(fi))
+	}
+
+	fn attr_lines(fi: &FileInfo) -> Vec<Line<'static>> {
 		let mut vec = Vec::new();
 		if let Ok(md) = fi.metadata.as_ref() {
 			vec.push(tui_line(
 				"Size: ",
 				human_bytes::human_bytes(md.len() as f64).as_str()
 			));
-			if let Some(t) = DateTime::from_timestamp(md.mtime(), 0) {
-				vec.push(tui_line(
-					"MTime: ",
-					t.naive_local()
-						.format("%Y-%m-%d %H:%M:%S")
-						.to_string()
-						.as_str()
-				));
+			if let Some(mtime) = format_mtime(md) {
+				vec.push(tui_line("MTime: ", mtime.as_str()));
 			}
 		}
 
@@ -172,11 +481,15 @@ pub trait Viewer {
 			None => {}
 		};
 
-		if vec.is_empty() {
-			None
-		} else {
-			Some(Paragraph::new(Text::from(vec)).wrap(Wrap { trim: true }))
-		}
+		vec
+	}
+}
+
+fn attrs_paragraph(lines: Vec<Line<'static>>) -> Option<Paragraph<'static>> {
+	if lines.is_empty() {
+		None
+	} else {
+		Some(Paragraph::new(Text::from(lines)).wrap(Wrap { trim: true }))
 	}
 }
 
@@ -189,3 +502,89 @@ fn tui_line(title: &'static str, content: &str) -> Line<'static> {
 		ratatui::text::Span::styled(String::from(content), Style::new()),
 	])
 }
+
+/// Centers the line a grep result points at, the top for anything else
+fn target_scroll(path: &FilePath, page: u16) -> Scroll {
+	let line = path
+		.position()
+		.map_or(0, |(row, _)| row.saturating_sub(1 + page as usize / 2));
+	Scroll { line, column: 0 }
+}
+
+/// libmagic cookies can't be shared between threads, each is used by one at a time
+struct Magic {
+	/// The description, e.g. `ASCII text`
+	desc: Option<Mutex<Cookie<Load>>>,
+	/// The mime encoding only, `binary` for anything that isn't text
+	encoding: Option<Mutex<Cookie<Load>>>,
+	/// Only opened when preview commands are picked by mime type
+	mime: Option<Mutex<Cookie<Load>>>
+}
+
+#[derive(Default)]
+struct Sniffed {
+	desc: Option<String>,
+	binary: bool,
+	mime: Option<String>
+}
+
+impl Magic {
+	fn sniff(&self, path: &Path) -> Sniffed {
+		Sniffed {
+			desc: sniff_with(self.desc.as_ref(), path),
+			binary: sniff_with(self.encoding.as_ref(), path).map_or(false, |e| e == "binary"),
+			mime: sniff_with(self.mime.as_ref(), path)
+		}
+	}
+}
+
+fn sniff_with(cookie: Option<&Mutex<Cookie<Load>>>, path: &Path) -> Option<String> {
+	cookie?.lock().unwrap().file(path).ok()
+}
+
+fn open_cookie(flags: magic::cookie::Flags) -> Option<Mutex<Cookie<Load>>> {
+	let cookie = magic::Cookie::open(flags).ok()?;
+	// load the system's default database
+	let database = &Default::default();
+	cookie.load(database).ok().map(Mutex::new)
+}
+
+fn format_mtime(md: &Metadata) -> Option<String> {
+	format_timestamp(md.mtime())
+}
+
+fn format_timestamp(secs: i64) -> Option<String> {
+	DateTime::from_timestamp(secs, 0).map(|t| {
+		t.naive_local()
+			.format("%Y-%m-%d %H:%M:%S")
+			.to_string()
+	})
+}
+
+/// The body on top, the attrs below it taking up to 5 lines
+fn split_attrs(rect: &Rect, attrs: Option<&Paragraph<'static>>) -> (Rect, Rect) {
+	let attrs_height = attrs
+		.map(|e| e.line_count(rect.width))
+		.unwrap_or(0)
+		.clamp(0, 5) as u16;
+
+	let tb = Layout::vertical([Constraint::Fill(1), Constraint::Max(attrs_height)]).split(*rect);
+	(tb[0], tb[1])
+}
+
+fn draw_with_attrs(
+	f: &mut Frame,
+	rect: &Rect,
+	body: Paragraph<'static>,
+	scroll: Scroll,
+	attrs: Option<&Paragraph<'static>>
+) {
+	let (body_area, attrs_area) = split_attrs(rect, attrs);
+
+	let offset = |n: usize| n.min(u16::MAX as usize) as u16;
+	f.render_widget(
+		body.scroll((offset(scroll.line), offset(scroll.column))),
+		body_area
+	);
+	attrs.map(|e| f.render_widget(e.clone(), attrs_area));
+}
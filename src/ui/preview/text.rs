@@ -1,50 +1,168 @@
 use std::{
-	path::Path,
+	io::{Read, SeekFrom},
+	num::NonZeroUsize,
+	path::{Path, PathBuf},
 	sync::{
-		atomic::{AtomicUsize, Ordering},
-		Arc
-	}
+		atomic::{AtomicBool, AtomicUsize, Ordering},
+		Arc, Mutex, OnceLock
+	},
+	time::SystemTime
 };
 
 use chin_tools::wrapper::anyhow::AResult;
+use lru::LruCache;
 use ratatui::{
 	layout::{Constraint, Layout, Rect},
-	text::{Line, Span, Text},
+	style::{Color, Style},
+	text::{Line, Span},
 	widgets::{Paragraph, Wrap},
 	Frame
 };
 use syntect::{
 	easy::HighlightLines,
-	highlighting::ThemeSet,
+	highlighting::{Theme, ThemeSet},
 	parsing::{SyntaxReference, SyntaxSet},
 	util::LinesWithEndings
 };
 use tokio::{
 	fs::File,
-	io::{AsyncBufReadExt, BufReader}
+	io::{AsyncBufRead, AsyncBufReadExt, AsyncSeekExt, BufReader}
+};
+use tracing::warn;
+
+use super::{tui_line, Scroll, ViewMsg, ViewType, Viewer};
+use crate::{
+	ansi,
+	config::config_dir,
+	fileinfo::FileInfo,
+	vendor::syntect_tui::{into_span, translate_colour}
 };
 
-use super::{ViewMsg, Viewer};
-use crate::{fileinfo::FileInfo, vendor::syntect_tui::into_span};
+/// The syntaxes and themes, loaded once and shared by every preview
+pub struct HighlightAssets {
+	pub syntaxes: SyntaxSet,
+	pub themes: &'static ThemeSet
+}
+
+static ASSETS: OnceLock<HighlightAssets> = OnceLock::new();
+/// Apart from the syntaxes, they are quick to load and checked before the tui starts
+static THEMES: OnceLock<ThemeSet> = OnceLock::new();
+
+impl HighlightAssets {
+	/// Loads the sets on first use, that takes a while, see [`HighlightAssets::preload`]
+	pub fn get() -> &'static Self {
+		ASSETS.get_or_init(Self::load)
+	}
+
+	/// The builtin syntaxes with the `.sublime-syntax` files of
+	/// `$XDG_CONFIG_HOME/ffp/syntaxes/` added
+	fn load() -> Self {
+		let mut syntaxes = SyntaxSet::load_defaults_newlines();
+		let themes = Self::themes();
+		let Some(dir) = config_dir() else {
+			return Self { syntaxes, themes };
+		};
+
+		let syntax_dir = dir.join("syntaxes");
+		if syntax_dir.is_dir() {
+			let mut builder = syntaxes.into_builder();
+			match builder.add_from_folder(&syntax_dir, true) {
+				Ok(()) => syntaxes = builder.build(),
+				Err(err) => {
+					warn!(
+						"unable to load syntaxes from {}: {}",
+						syntax_dir.display(),
+						err
+					);
+					syntaxes = SyntaxSet::load_defaults_newlines();
+				}
+			}
+		}
+
+		Self { syntaxes, themes }
+	}
+
+	/// The builtin themes with the `.tmTheme` files of `$XDG_CONFIG_HOME/ffp/themes/` added
+	fn themes() -> &'static ThemeSet {
+		THEMES.get_or_init(|| {
+			let mut themes = ThemeSet::load_defaults();
+			let Some(theme_dir) = config_dir().map(|dir| dir.join("themes")) else {
+				return themes;
+			};
+			if theme_dir.is_dir() {
+				if let Err(err) = themes.add_from_folder(&theme_dir) {
+					warn!(
+						"unable to load themes from {}: {}",
+						theme_dir.display(),
+						err
+					);
+				}
+			}
+			themes
+		})
+	}
+
+	/// Fails with the known themes when there is no theme called `name`
+	pub fn check_theme(name: &str) -> AResult<()> {
+		let themes = &Self::themes().themes;
+		if themes.contains_key(name) {
+			return Ok(());
+		}
+		let known = themes.keys().map(String::as_str).collect::<Vec<_>>();
+		anyhow::bail!(
+			"unknown preview theme `{}`, known themes: {}",
+			name,
+			known.join(", ")
+		)
+	}
+
+	/// Loads the sets in the background so the first preview doesn't wait for them
+	pub fn preload() {
+		std::thread::spawn(|| {
+			Self::get();
+		});
+	}
+}
+
+const DEFAULT_THEME: &str = "base16-ocean.light";
 
 pub struct TextHighlighter {
-	syntaxes: SyntaxSet
+	theme: String
 }
 
 impl TextHighlighter {
-	fn new() -> Self {
-		Self {
-			syntaxes: Default::default()
-		}
+	fn new(theme: String) -> Self {
+		Self { theme }
+	}
+
+	/// The theme was checked at startup, see [`HighlightAssets::check_theme`], the default
+	/// one only stands in when the preview was built without that
+	fn theme<'a>(&self, assets: &'a HighlightAssets) -> &'a Theme {
+		let themes = &assets.themes.themes;
+		themes.get(&self.theme).unwrap_or(&themes[DEFAULT_THEME])
+	}
+
+	/// The theme's current line color, marks the line a grep result points at
+	fn line_highlight(&self) -> Color {
+		THEMES
+			.get()
+			.and_then(|themes| themes.themes.get(&self.theme))
+			.and_then(|theme| theme.settings.line_highlight)
+			.and_then(translate_colour)
+			.unwrap_or(Color::DarkGray)
 	}
 
 	// like https://github.com/sxyazi/yazi/blob/main/yazi-plugin/src/external/highlighter.rs
-	async fn detect_syntax(&self, filepath: &Path) -> AResult<&SyntaxReference> {
+	async fn detect_syntax(
+		&self,
+		assets: &'static HighlightAssets,
+		filepath: &Path
+	) -> AResult<&'static SyntaxReference> {
 		if let Some(filename) = filepath
 			.file_name()
 			.map(|e| e.to_string_lossy().to_string())
 		{
-			if let Some(s) = self.syntaxes.find_syntax_by_extension(filename.as_str()) {
+			if let Some(s) = assets.syntaxes.find_syntax_by_extension(filename.as_str()) {
 				return Ok(s);
 			}
 		}
@@ -53,7 +171,7 @@ impl TextHighlighter {
 			.extension()
 			.map(|e| e.to_string_lossy().to_string())
 		{
-			if let Some(s) = self.syntaxes.find_syntax_by_extension(ext.as_str()) {
+			if let Some(s) = assets.syntaxes.find_syntax_by_extension(ext.as_str()) {
 				return Ok(s);
 			}
 		}
@@ -61,7 +179,8 @@ impl TextHighlighter {
 		let mut line = String::new();
 		let mut reader = BufReader::new(File::open(&filepath).await?);
 		reader.read_line(&mut line).await?;
-		self.syntaxes
+		assets
+			.syntaxes
 			.find_syntax_by_first_line(&line)
 			.ok_or_else(|| anyhow::anyhow!("No syntax found"))
 	}
@@ -71,7 +190,16 @@ impl TextHighlighter {
 		filepath: &Path,
 		file_content: String
 	) -> AResult<Vec<Vec<ratatui::text::Span<'static>>>> {
-		match self.translate_style(filepath, file_content.as_str()).await {
+		// colored logs and captured terminal output bring their own colors
+		if file_content.contains('\x1b') {
+			return Ok(ansi::parse_text(&file_content)
+				.lines
+				.into_iter()
+				.map(|line| line.spans)
+				.collect());
+		}
+
+		match self.translate_style(filepath, file_content.clone()).await {
 			Ok(vec) => Ok(vec),
 			Err(_) => Ok(self.translate_plain(&file_content))
 		}
@@ -92,39 +220,360 @@ impl TextHighlighter {
 	async fn translate_style(
 		&self,
 		filepath: &Path,
-		content: &str
+		content: String
 	) -> AResult<Vec<Vec<ratatui::text::Span<'static>>>> {
-		let ps = SyntaxSet::load_defaults_newlines();
-		let ts = ThemeSet::load_defaults();
-		let syntax = self.detect_syntax(filepath).await?;
-		let mut h = HighlightLines::new(syntax, &ts.themes["base16-ocean.light"]);
-		let mut lines: Vec<Vec<Span<'static>>> = vec![];
-
-		for line in LinesWithEndings::from(content) {
-			// LinesWithEndings enables use of newlines mode
-			let line_spans: Vec<ratatui::text::Span> = h
-				.highlight_line(line, &ps)
-				.unwrap()
-				.into_iter()
-				.filter_map(|(style, s)| into_span((style, s.replace("\t", "  ").as_str())).ok())
-				.collect();
-			lines.push(line_spans);
+		// only the first preview may have to wait for the sets
+		let assets = tokio::task::spawn_blocking(HighlightAssets::get).await?;
+		let syntax = self.detect_syntax(assets, filepath).await?;
+		let theme = self.theme(assets);
+
+		// highlighting a window is too slow for the async workers
+		tokio::task::spawn_blocking(move || {
+			let mut h = HighlightLines::new(syntax, theme);
+			let mut lines: Vec<Vec<Span<'static>>> = vec![];
+
+			for line in LinesWithEndings::from(&content) {
+				// LinesWithEndings enables use of newlines mode
+				let line_spans: Vec<ratatui::text::Span> = h
+					.highlight_line(line, &assets.syntaxes)?
+					.into_iter()
+					.filter_map(|(style, s)| {
+						into_span((style, s.replace("\t", "  ").as_str())).ok()
+					})
+					.collect();
+				lines.push(line_spans);
+			}
+
+			Ok::<_, anyhow::Error>(lines)
+		})
+		.await?
+	}
+}
+
+/// Lines read and highlighted around the viewport
+pub const WINDOW_LINES: usize = 1000;
+/// The byte offset of every `INDEX_STEP`th line is remembered to seek into big files
+const INDEX_STEP: usize = 1024;
+/// Longer lines are cut, a minified file would be read whole otherwise
+const MAX_LINE_BYTES: usize = 4096;
+
+/// What is known about the lines of one file, filled in by [`TextViewer::count_lines`]
+pub struct LineIndex {
+	/// Byte offsets of the lines `0, INDEX_STEP, 2 * INDEX_STEP, ...`
+	checkpoints: Mutex<Vec<u64>>,
+	total: OnceLock<usize>,
+	/// Start of the window the stage asked for last
+	wanted: AtomicUsize
+}
+
+impl LineIndex {
+	pub fn new() -> Self {
+		Self {
+			checkpoints: Mutex::new(vec![0]),
+			total: OnceLock::new(),
+			wanted: AtomicUsize::new(0)
 		}
+	}
+
+	pub fn total(&self) -> Option<usize> {
+		self.total.get().copied()
+	}
 
-		Ok(lines)
+	pub fn want(&self, start: usize) {
+		self.wanted.store(start, Ordering::Relaxed);
 	}
+
+	/// The closest indexed (line, byte offset) at or before `line`
+	fn seek_point(&self, line: usize) -> (usize, u64) {
+		let checkpoints = self.checkpoints.lock().unwrap();
+		let i = (line / INDEX_STEP).min(checkpoints.len() - 1);
+		(i * INDEX_STEP, checkpoints[i])
+	}
+}
+
+/// The highlighted lines `start..start + lines.len()` of a file
+pub struct TextWindow {
+	pub start: usize,
+	pub lines: Vec<Line<'static>>,
+	/// The window reaches the end of the file
+	pub eof: bool,
+	/// Width of the widest line
+	pub width: usize,
+	pub index: Arc<LineIndex>
+}
+
+impl TextWindow {
+	/// The line count once known, until then enough to scroll into the next window
+	pub fn total(&self) -> usize {
+		let end = self.start + self.lines.len();
+		match self.index.total() {
+			Some(total) => total,
+			None if self.eof => end,
+			None => end + WINDOW_LINES / 2
+		}
+	}
+
+	/// Whether `first..first + height` needs lines that were not read
+	pub fn misses(&self, first: usize, height: usize) -> bool {
+		first < self.start || (!self.eof && first + height > self.start + self.lines.len())
+	}
+}
+
+/// Reads one line without its terminator into `buf`, keeping at most `MAX_LINE_BYTES` of
+/// it. `false` at the end of the file
+async fn read_line_capped<R: AsyncBufRead + Unpin>(
+	reader: &mut R,
+	buf: &mut Vec<u8>
+) -> std::io::Result<bool> {
+	buf.clear();
+	let mut read_any = false;
+	loop {
+		let (used, done) = {
+			let available = reader.fill_buf().await?;
+			if available.is_empty() {
+				return Ok(read_any);
+			}
+			read_any = true;
+
+			let (chunk, used, done) = match available.iter().position(|b| *b == b'\n') {
+				Some(i) => (&available[..i], i + 1, true),
+				None => (available, available.len(), false)
+			};
+			let room = MAX_LINE_BYTES.saturating_sub(buf.len());
+			buf.extend_from_slice(&chunk[..chunk.len().min(room)]);
+			(used, done)
+		};
+		reader.consume(used);
+
+		if done {
+			if buf.last() == Some(&b'\r') {
+				buf.pop();
+			}
+			return Ok(true);
+		}
+	}
+}
+
+/// Highlighted windows kept around, moving through the list shows them right away
+const CACHED_WINDOWS: usize = 64;
+
+/// A file's window is highlighted again once the file or the theme changed
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct WindowKey {
+	path: PathBuf,
+	mtime: SystemTime,
+	theme: String,
+	start: usize
+}
+
+struct CachedWindow {
+	lines: Vec<Line<'static>>,
+	eof: bool,
+	width: usize
 }
 
 pub struct TextViewer {
 	highlighter: Arc<TextHighlighter>,
-	wrap: bool
+	windows: Mutex<LruCache<WindowKey, Arc<CachedWindow>>>,
+	wrap: bool,
+	line_numbers: AtomicBool
 }
 
 impl TextViewer {
-	pub fn new() -> Self {
+	pub fn new(wrap: bool, line_numbers: bool, theme: String) -> Self {
 		Self {
-			highlighter: Arc::new(TextHighlighter::new()),
-			wrap: false
+			highlighter: Arc::new(TextHighlighter::new(theme)),
+			windows: Mutex::new(LruCache::new(NonZeroUsize::new(CACHED_WINDOWS).unwrap())),
+			wrap,
+			line_numbers: AtomicBool::new(line_numbers)
+		}
+	}
+
+	pub fn toggle_line_numbers(&self) {
+		self.line_numbers.fetch_xor(true, Ordering::Relaxed);
+	}
+
+	/// Reads and highlights a window, or takes it from the cache
+	async fn window(
+		&self,
+		fileinfo: &FileInfo,
+		start: usize,
+		index: &LineIndex
+	) -> Option<Arc<CachedWindow>> {
+		let key = fileinfo
+			.metadata
+			.as_ref()
+			.ok()
+			.and_then(|md| md.modified().ok())
+			.map(|mtime| WindowKey {
+				path: fileinfo.path().to_path_buf(),
+				mtime,
+				theme: self.highlighter.theme.clone(),
+				start
+			});
+		if let Some(cached) = key
+			.as_ref()
+			.and_then(|key| self.windows.lock().unwrap().get(key).cloned())
+		{
+			return Some(cached);
+		}
+
+		let (lines, eof) = Self::read_window(fileinfo.path(), start, index).await.ok()?;
+		let lines = self
+			.highlighter
+			.translate(fileinfo.path(), lines.join("\n"))
+			.await
+			.ok()?
+			.into_iter()
+			.map(Line::from)
+			.collect::<Vec<_>>();
+		let width = lines.iter().map(Line::width).max().unwrap_or(0);
+
+		let window = Arc::new(CachedWindow { lines, eof, width });
+		if let Some(key) = key {
+			self.windows.lock().unwrap().put(key, window.clone());
+		}
+		Some(window)
+	}
+
+	/// Up to `WINDOW_LINES` lines from `start`, decoded lossily so a cut multi-byte char
+	/// can't fail the whole preview
+	async fn read_window(
+		path: &Path,
+		start: usize,
+		index: &LineIndex
+	) -> std::io::Result<(Vec<String>, bool)> {
+		let (mut line, offset) = index.seek_point(start);
+		let mut file = File::open(path).await?;
+		file.seek(SeekFrom::Start(offset)).await?;
+		let mut reader = BufReader::new(file);
+		let mut buf = vec![];
+
+		while line < start {
+			if !read_line_capped(&mut reader, &mut buf).await? {
+				return Ok((vec![], true));
+			}
+			line += 1;
+		}
+
+		let mut lines = vec![];
+		while lines.len() < WINDOW_LINES {
+			if !read_line_capped(&mut reader, &mut buf).await? {
+				return Ok((lines, true));
+			}
+			lines.push(String::from_utf8_lossy(&buf).into_owned());
+		}
+		Ok((lines, false))
+	}
+
+	/// Reads and highlights the window of `fileinfo` starting at line `start`
+	pub async fn load(
+		&self,
+		fileinfo: FileInfo,
+		start: usize,
+		index: Arc<LineIndex>,
+		ticket: usize,
+		ticket_holder: Arc<AtomicUsize>
+	) -> Option<ViewMsg> {
+		if ticket != ticket_holder.load(Ordering::Relaxed) {
+			return None;
+		}
+		let window = self.window(&fileinfo, start, &index).await?;
+
+		let mut attrs = Self::attr_lines(&fileinfo);
+		let total = index
+			.total()
+			.map_or("counting...".to_string(), |total| total.to_string());
+		attrs.push(tui_line("Lines: ", &total));
+
+		if ticket != ticket_holder.load(Ordering::Relaxed) {
+			return None;
+		}
+
+		Some(ViewMsg {
+			fileinfo,
+			body: ViewType::Text(TextWindow {
+				start,
+				lines: window.lines.clone(),
+				eof: window.eof,
+				width: window.width,
+				index
+			}),
+			attr: super::attrs_paragraph(attrs)
+		})
+	}
+
+	/// Counts the lines of `path` into `index`, `false` if it was cancelled or failed
+	pub async fn count_lines(
+		path: &Path,
+		index: Arc<LineIndex>,
+		ticket: usize,
+		ticket_holder: Arc<AtomicUsize>
+	) -> bool {
+		let path = path.to_path_buf();
+		tokio::task::spawn_blocking(move || -> std::io::Result<bool> {
+			let mut file = std::fs::File::open(path)?;
+			let mut buf = vec![0u8; 1 << 16];
+			let mut offset = 0u64;
+			let mut lines = 0usize;
+			let mut last = b'\n';
+
+			loop {
+				if ticket != ticket_holder.load(Ordering::Relaxed) {
+					return Ok(false);
+				}
+				let n = file.read(&mut buf)?;
+				if n == 0 {
+					break;
+				}
+				for (i, b) in buf[..n].iter().enumerate() {
+					if *b == b'\n' {
+						lines += 1;
+						if lines % INDEX_STEP == 0 {
+							index
+								.checkpoints
+								.lock()
+								.unwrap()
+								.push(offset + i as u64 + 1);
+						}
+					}
+				}
+				last = buf[n - 1];
+				offset += n as u64;
+			}
+
+			// the last line may not end with a newline
+			let _ = index.total.set(lines + usize::from(last != b'\n'));
+			Ok(true)
+		})
+		.await
+		.map_or(false, |res| res.unwrap_or(false))
+	}
+
+	/// The window at `start` right away, then the window wanted by then again with the line
+	/// count once the whole file was read
+	pub async fn stream(
+		&self,
+		fileinfo: FileInfo,
+		start: usize,
+		ticket: usize,
+		ticket_holder: Arc<AtomicUsize>,
+		sender: flume::Sender<ViewMsg>
+	) {
+		let index = Arc::new(LineIndex::new());
+		index.want(start);
+		if let Some(msg) = self
+			.load(fileinfo.clone(), start, index.clone(), ticket, ticket_holder.clone())
+			.await
+		{
+			let _ = sender.send(msg);
+		}
+
+		if Self::count_lines(fileinfo.path(), index.clone(), ticket, ticket_holder.clone()).await {
+			let start = index.wanted.load(Ordering::Relaxed);
+			if let Some(msg) = self.load(fileinfo, start, index, ticket, ticket_holder).await {
+				let _ = sender.send(msg);
+			}
 		}
 	}
 }
@@ -137,53 +586,83 @@ impl Viewer for TextViewer {
 		fileinfo: FileInfo,
 		ticket: usize,
 		ticket_holder: Arc<AtomicUsize>,
-		text: Option<String>
+		_text: Option<String>
 	) -> Option<ViewMsg> {
-		let text = self
-			.highlighter
-			.translate(fileinfo.path(), text.unwrap())
+		self.load(fileinfo, 0, Arc::new(LineIndex::new()), ticket, ticket_holder)
 			.await
-			.unwrap()
-			.into_iter()
-			.map(|spans| Line::from(spans))
-			.collect::<Text<'static>>();
-		let paragraph = if self.wrap {
-			Paragraph::new(text).wrap(Wrap::default())
-		} else {
-			Paragraph::new(text)
-		};
-
-		let attrs = Self::attrs(&fileinfo);
-
-		if ticket != ticket_holder.load(Ordering::Relaxed) {
-			None
-		} else {
-			Some(ViewMsg {
-				fileinfo,
-				body: super::ViewType::Text(paragraph),
-				attr: attrs
-			})
-		}
 	}
 
 	fn handle_event(&mut self, event: crossterm::event::Event) {}
 
-	fn draw(&self, view_msg: &ViewMsg, cursor: usize, f: &mut Frame, rect: &Rect) {
-		let attrs = view_msg.attr.as_ref();
-		let attrs_height = attrs
-			.as_ref()
-			.map(|e| e.line_count(rect.width))
-			.unwrap_or(0)
-			.clamp(0, 5) as u16;
+	fn draw(&self, view_msg: &ViewMsg, scroll: Scroll, f: &mut Frame, rect: &Rect) {
+		if let ViewType::Text(window) = &view_msg.body {
+			let target = view_msg.fileinfo.path.position().map(|(row, _)| row - 1);
+			let target_style = Style::new().bg(self.highlighter.line_highlight());
 
-		let tb =
-			Layout::vertical([Constraint::Fill(1), Constraint::Max(attrs_height)]).split(*rect);
+			// only the visible lines, wrapping them can't shift the window
+			let first = scroll.line.max(window.start);
+			let visible = window
+				.lines
+				.iter()
+				.skip(first - window.start)
+				.take(rect.height as usize)
+				.enumerate()
+				.map(|(i, line)| {
+					if target == Some(first + i) {
+						// the spans carry the theme's background, the line's style wouldn't show
+						Line::from(
+							line.spans
+								.iter()
+								.map(|span| span.clone().patch_style(target_style))
+								.collect::<Vec<_>>()
+						)
+					} else {
+						line.clone()
+					}
+				})
+				.collect::<Vec<_>>();
 
-		match &view_msg.body {
-			super::ViewType::Text(text) => f.render_widget(text.clone(), tb[0]),
-			_ => {}
-		}
+			let (body_area, attrs_area) = super::split_attrs(rect, view_msg.attr.as_ref());
+			let text_area = if self.line_numbers.load(Ordering::Relaxed) {
+				let digits = window.total().max(1).to_string().len();
+				let areas = Layout::horizontal([
+					Constraint::Length(digits as u16 + 1),
+					Constraint::Fill(1)
+				])
+				.split(body_area);
+
+				// a wrapped line takes more rows, its number stays on the first
+				let mut numbers = vec![];
+				for (i, line) in visible.iter().enumerate() {
+					let style = if target == Some(first + i) {
+						target_style
+					} else {
+						Style::new().fg(Color::DarkGray)
+					};
+					numbers.push(Line::styled(format!("{:>digits$}", first + i + 1), style));
+					if self.wrap {
+						let rows = Paragraph::new(line.clone())
+							.wrap(Wrap::default())
+							.line_count(areas[1].width);
+						numbers.extend((1..rows).map(|_| Line::raw("")));
+					}
+				}
+				f.render_widget(Paragraph::new(numbers), areas[0]);
+				areas[1]
+			} else {
+				body_area
+			};
 
-		attrs.map(|e| f.render_widget(e.clone(), tb[1]));
+			let body = if self.wrap {
+				Paragraph::new(visible).wrap(Wrap::default())
+			} else {
+				Paragraph::new(visible)
+			};
+			let column = scroll.column.min(u16::MAX as usize) as u16;
+			f.render_widget(body.scroll((0, column)), text_area);
+			if let Some(attrs) = view_msg.attr.as_ref() {
+				f.render_widget(attrs.clone(), attrs_area);
+			}
+		}
 	}
 }
@@ -1,22 +1,32 @@
 use ratatui::{layout::Rect, widgets::Paragraph};
 
 use super::{Component};
-use crate::dirwalker::FindType;
+use crate::{dirwalker::FindType, matcher::Scope};
 
 pub enum StatusIn {
 	CWD(String),
 	ShowType(FindType),
 	ShowHide(bool),
+	Ignore(bool),
+	Sort(bool),
+	Scope(Scope),
 	Total(usize),
-	FilterSize(usize)
+	FilterSize(usize),
+	/// Something to tell the user, e.g. a failed command
+	Message(Option<String>)
 }
 
 pub struct Status {
 	cwd: String,
 	show_type: FindType,
 	show_hide: bool,
+	ignore: bool,
+	sort: bool,
+	scope: Scope,
 	total: usize,
-	filter_size: usize
+	filter_size: usize,
+	marked: usize,
+	message: Option<String>
 }
 
 impl Status {
@@ -24,9 +34,14 @@ impl Status {
 		Self {
 			cwd: cwd.to_string(),
 			show_hide: Default::default(),
+			ignore: false,
+			sort: false,
+			scope: Scope::default(),
 			show_type: Default::default(),
 			filter_size: 0,
-			total: 0
+			total: 0,
+			marked: 0,
+			message: None
 		}
 	}
 
@@ -37,6 +52,10 @@ impl Status {
 	pub fn set_filter_count(&mut self, num: usize) {
 		self.filter_size = num;
 	}
+
+	pub fn set_marked_count(&mut self, num: usize) {
+		self.marked = num;
+	}
 }
 
 impl Component for Status {
@@ -59,10 +78,36 @@ impl Component for Status {
 		};
 
 		let hide_type = if self.show_hide { "[H]" } else { "" };
+		let ignore_type = if self.ignore { "[I]" } else { "" };
+		let sort_type = if self.sort { "[S]" } else { "" };
+		let scope_type = match self.scope {
+			Scope::Path => "",
+			Scope::Name => "[N]",
+			Scope::Dir => "[D]"
+		};
+		let marked = if self.marked > 0 {
+			format!(" (+{})", self.marked)
+		} else {
+			"".to_string()
+		};
+
+		let message = self
+			.message
+			.as_ref()
+			.map_or("".to_string(), |msg| format!(" | {}", msg));
 
 		Paragraph::new(format!(
-			"-- [{}]{} {}/{} {}",
-			find_type, hide_type, self.filter_size, self.total, self.cwd
+			"-- [{}]{}{}{}{} {}/{}{} {}{}",
+			find_type,
+			hide_type,
+			ignore_type,
+			sort_type,
+			scope_type,
+			self.filter_size,
+			self.total,
+			marked,
+			self.cwd,
+			message
 		))
 	}
 
@@ -77,12 +122,24 @@ impl Component for Status {
 			StatusIn::ShowHide(sh) => {
 				self.show_hide = sh;
 			}
+			StatusIn::Ignore(ignore) => {
+				self.ignore = ignore;
+			}
+			StatusIn::Sort(sort) => {
+				self.sort = sort;
+			}
+			StatusIn::Scope(scope) => {
+				self.scope = scope;
+			}
 			StatusIn::Total(total) => {
 				self.total = total;
 			}
 			StatusIn::FilterSize(f) => {
 				self.filter_size = f;
 			}
+			StatusIn::Message(msg) => {
+				self.message = msg;
+			}
 		}
 	}
 }
@@ -1,15 +1,19 @@
 use std::rc::Rc;
 
 use ratatui::style::{Color, Style};
+use serde::Deserialize;
 
 pub type SharedTheme = Rc<Theme>;
 
-#[derive(Debug, Clone)]
+/// Colors are names like `light-yellow`, `#rrggbb` or a 256 color index
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Theme {
 	selection_bg: Color,
 	selection_fg: Color,
 	disabled_fg: Color,
-	command_fg: Color
+	command_fg: Color,
+	marker_fg: Color
 }
 
 impl Default for Theme {
@@ -18,7 +22,8 @@ impl Default for Theme {
 			selection_bg: Color::Blue,
 			selection_fg: Color::Yellow,
 			disabled_fg: Default::default(),
-			command_fg: Color::LightYellow
+			command_fg: Color::LightYellow,
+			marker_fg: Color::LightGreen
 		}
 	}
 }
@@ -28,10 +33,25 @@ impl Theme {
 		Style::default().fg(self.selection_bg)
 	}
 
+	pub fn marker(&self) -> Style {
+		Style::default().fg(self.marker_fg)
+	}
+
 	pub fn block(&self, focus: bool) -> Style {
 		Style::default()
 	}
 
+	/// [`Theme::text`] over a candidate's own color, which only gives way to the match and
+	/// selection colors
+	pub fn ansi_text(&self, ansi: Style, enabled: bool, selected: bool) -> Style {
+		let style = self.text(false, false).patch(ansi);
+		if enabled || selected {
+			style.patch(self.text(enabled, selected))
+		} else {
+			style
+		}
+	}
+
 	pub fn text(&self, enabled: bool, selected: bool) -> Style {
 		match (enabled, selected) {
 			(false, false) => Style::default().fg(self.disabled_fg),